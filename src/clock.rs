@@ -1,4 +1,10 @@
 use std::cell::RefCell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A source of the current time, in seconds.
+pub trait Clock {
+    fn now(&self) -> u64;
+}
 
 #[derive(Default, Clone, Copy)]
 pub struct TestClock {
@@ -15,6 +21,11 @@ impl TestClock {
         self.t = self.t.saturating_add(secs);
     }
 }
+impl Clock for TestClock {
+    fn now(&self) -> u64 {
+        self.t
+    }
+}
 
 thread_local! {
     static TEST_CLOCK: RefCell<TestClock> = RefCell::new(TestClock::new());
@@ -29,3 +40,30 @@ pub fn wait(secs: u64) {
 pub fn clock_reset(ts: u64) {
     TEST_CLOCK.with(|c| c.borrow_mut().t = ts);
 }
+
+/// Reads the thread-local test clock driven by [`wait`] and [`clock_reset`].
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ThreadClock;
+impl Clock for ThreadClock {
+    fn now(&self) -> u64 {
+        now()
+    }
+}
+
+/// Wall-clock time, in seconds since the Unix epoch.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+/// Caller-supplied time, e.g. a closure returning the host's block timestamp.
+impl<F: Fn() -> u64> Clock for F {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
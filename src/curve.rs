@@ -0,0 +1,182 @@
+//! Vesting curves: how much of an amount is still vesting some time after it started.
+//!
+//! A stream keeps the same accounting whatever its curve. The curve starts at the last deposit
+//! or rate change with everything then still vesting ("snapshot, then add"), and balances are
+//! read off it. That only reproduces per-deposit vesting when the curve is memoryless, which
+//! [`VestingCurve::is_deposit_neutral`] reports: for the other curves a deposit restarts the
+//! schedule of whatever was still vesting.
+
+use crate::{DecayRate, FixedAmount};
+
+pub trait VestingCurve {
+    /// Whether a deposit leaves the vesting of earlier deposits unchanged.
+    fn is_deposit_neutral(&self) -> bool;
+
+    /// How much of `principal` is still vesting `elapsed` seconds after it started. Must not
+    /// increase with `elapsed`.
+    fn still_vesting(&self, principal: FixedAmount, elapsed: u64) -> FixedAmount;
+}
+
+/// `P·e^{-λt}`. Deposit-neutral.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExponentialCurve(pub DecayRate);
+
+impl VestingCurve for ExponentialCurve {
+    fn is_deposit_neutral(&self) -> bool {
+        true
+    }
+
+    fn still_vesting(&self, principal: FixedAmount, elapsed: u64) -> FixedAmount {
+        self.0.decay_fixed(principal, elapsed)
+    }
+}
+
+/// Nothing vests during the cliff, then everything vests evenly until `duration` has passed,
+/// counting from the start. The part for the cliff itself vests the moment it ends.
+/// Not deposit-neutral.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinearCurve {
+    cliff: u64,
+    duration: u64,
+}
+
+impl LinearCurve {
+    pub const fn new(duration: u64) -> Self {
+        Self { cliff: 0, duration }
+    }
+
+    /// Lock everything for the first `cliff` seconds, which must not exceed the duration.
+    pub fn with_cliff(self, cliff: u64) -> Self {
+        assert!(
+            cliff <= self.duration,
+            "cliff longer than the vesting duration"
+        );
+        Self { cliff, ..self }
+    }
+
+    pub const fn cliff(&self) -> u64 {
+        self.cliff
+    }
+
+    pub const fn duration(&self) -> u64 {
+        self.duration
+    }
+}
+
+impl VestingCurve for LinearCurve {
+    fn is_deposit_neutral(&self) -> bool {
+        false
+    }
+
+    fn still_vesting(&self, principal: FixedAmount, elapsed: u64) -> FixedAmount {
+        if elapsed < self.cliff {
+            principal
+        } else if elapsed >= self.duration {
+            FixedAmount::default()
+        } else {
+            principal.mul_ratio(self.duration - elapsed, self.duration)
+        }
+    }
+}
+
+/// Vests an equal part at the end of each of `steps` periods (epochs). Not deposit-neutral.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StepCurve {
+    period: u64,
+    steps: u64,
+}
+
+impl StepCurve {
+    pub const fn new(period: u64, steps: u64) -> Self {
+        Self { period, steps }
+    }
+
+    pub const fn period(&self) -> u64 {
+        self.period
+    }
+
+    pub const fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+impl VestingCurve for StepCurve {
+    fn is_deposit_neutral(&self) -> bool {
+        false
+    }
+
+    fn still_vesting(&self, principal: FixedAmount, elapsed: u64) -> FixedAmount {
+        let done = elapsed
+            .checked_div(self.period)
+            .unwrap_or(u64::MAX)
+            .min(self.steps);
+        if done == self.steps {
+            return FixedAmount::default();
+        }
+        principal.mul_ratio(self.steps - done, self.steps)
+    }
+}
+
+/// The curve of a stream. Persisted with it, so only the curves above are supported.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Curve {
+    Exponential(ExponentialCurve),
+    Linear(LinearCurve),
+    Step(StepCurve),
+}
+
+impl Curve {
+    /// The decay rate of an exponential curve.
+    pub fn decay_rate(&self) -> Option<DecayRate> {
+        match self {
+            Self::Exponential(ExponentialCurve(rate)) => Some(*rate),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Self::Exponential(ExponentialCurve::default())
+    }
+}
+
+impl From<ExponentialCurve> for Curve {
+    fn from(curve: ExponentialCurve) -> Self {
+        Self::Exponential(curve)
+    }
+}
+
+impl From<LinearCurve> for Curve {
+    fn from(curve: LinearCurve) -> Self {
+        Self::Linear(curve)
+    }
+}
+
+impl From<StepCurve> for Curve {
+    fn from(curve: StepCurve) -> Self {
+        Self::Step(curve)
+    }
+}
+
+impl VestingCurve for Curve {
+    fn is_deposit_neutral(&self) -> bool {
+        match self {
+            Self::Exponential(c) => c.is_deposit_neutral(),
+            Self::Linear(c) => c.is_deposit_neutral(),
+            Self::Step(c) => c.is_deposit_neutral(),
+        }
+    }
+
+    fn still_vesting(&self, principal: FixedAmount, elapsed: u64) -> FixedAmount {
+        match self {
+            Self::Exponential(c) => c.still_vesting(principal, elapsed),
+            Self::Linear(c) => c.still_vesting(principal, elapsed),
+            Self::Step(c) => c.still_vesting(principal, elapsed),
+        }
+    }
+}
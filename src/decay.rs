@@ -0,0 +1,297 @@
+//! Deterministic fixed-point exponential decay.
+//!
+//! Rates are stored as unsigned Q64.64 numbers (64 integer bits, 64 fractional bits), and
+//! `e^{-λΔt}` is evaluated with integer arithmetic only, so every platform computes the same
+//! result bit for bit.
+//!
+//! # Error bounds
+//!
+//! `λΔt` is computed exactly for the stored (quantized) rate and split into `k·ln2 + r` with
+//! `0 <= r < ln2`. `e^{-r}` is evaluated with a truncated Taylor series and the principal is
+//! multiplied by it before being shifted right by `k`. The relative error of the computed factor
+//! is below `2^-56` for every input, so `decay(P, Δt)` never differs from the exact
+//! `floor(P·e^{-λΔt})` by more than `ceil(P·e^{-λΔt}·2^-56)`. For principals below `2^56`
+//! that is at most one unit.
+//!
+//! Converting an `f64` rate into Q64.64 truncates it to a multiple of `2^-64` per second.
+
+use std::time::Duration;
+
+use crate::ShortHalfLife;
+
+/// `1.0` in Q64.64.
+const ONE: u128 = 1 << 64;
+
+/// `ln 2` in Q64.64, rounded down.
+const LN_2: u128 = 0xB172_17F7_D1CF_79AB;
+
+/// A per-second decay rate `λ`, stored as Q64.64 fixed point.
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecayRate(u128);
+
+impl DecayRate {
+    /// Build a rate from its raw Q64.64 representation.
+    pub const fn from_bits(bits: u128) -> Self {
+        Self(bits)
+    }
+
+    /// The raw Q64.64 representation.
+    pub const fn to_bits(self) -> u128 {
+        self.0
+    }
+
+    /// Quantize a floating point rate. Negative and NaN rates become zero.
+    pub fn from_f64(rate: f64) -> Self {
+        // Scaling by a power of two is exact, and the saturating cast is fully specified.
+        Self((rate * ONE as f64) as u128)
+    }
+
+    /// Approximate the rate as a float, for display purposes.
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / ONE as f64
+    }
+
+    /// The rate whose half-life is `half_life`, rounded down so that exactly one half-life
+    /// never decays past half. A zero half-life vests everything after one second.
+    pub fn from_half_life(half_life: HalfLife) -> Self {
+        match half_life.secs {
+            0 => Self(u128::MAX),
+            secs => Self(LN_2 / secs as u128),
+        }
+    }
+
+    /// The half-life `ln 2 / λ`, rounded down to whole seconds. Round-trips exactly through
+    /// [`DecayRate::from_half_life`] for half-lives under a century. A zero rate never halves.
+    pub fn half_life(self) -> HalfLife {
+        match self.0 {
+            0 => HalfLife::seconds(u64::MAX),
+            rate => HalfLife::seconds((LN_2 / rate).try_into().unwrap_or(u64::MAX)),
+        }
+    }
+
+    /// `floor(principal · e^{-λ·dt})`, within the bounds documented on this module.
+    pub fn decay(self, principal: u128, dt: u64) -> u128 {
+        self.decay_fixed(FixedAmount::from_whole(principal), dt)
+            .floor()
+    }
+
+    /// `value · e^{-λ·dt}`, keeping 64 fractional bits. Results below `2^-64` flush to zero.
+    pub fn decay_fixed(self, value: FixedAmount, dt: u64) -> FixedAmount {
+        if dt == 0 || self.0 == 0 {
+            return value;
+        }
+        // λ·dt beyond 2^64 (real units) decays anything to zero.
+        let Some(x) = self.0.checked_mul(dt as u128) else {
+            return FixedAmount::default();
+        };
+        let k = x / LN_2;
+        if k >= 128 {
+            return FixedAmount::default();
+        }
+        mul_q64(value, exp_neg(x % LN_2)).shr(k as u32)
+    }
+}
+
+/// How a fractional amount still vesting becomes a whole number of tokens.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RoundingMode {
+    /// Round the amount still vesting down, favouring the beneficiary.
+    #[default]
+    Floor,
+    /// Round the amount still vesting up, favouring the treasury.
+    Ceil,
+    /// Round to nearest, ties to even.
+    HalfEven,
+}
+
+/// A token amount with 64 fractional bits.
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct FixedAmount {
+    pub whole: u128,
+    pub frac: u64,
+}
+
+impl FixedAmount {
+    pub const fn from_whole(whole: u128) -> Self {
+        Self { whole, frac: 0 }
+    }
+
+    pub const fn floor(self) -> u128 {
+        self.whole
+    }
+
+    /// `amount / divisor`, truncated to 64 fractional bits.
+    pub fn from_ratio(amount: u128, divisor: u64) -> Self {
+        let divisor = u128::from(divisor);
+        Self {
+            whole: amount / divisor,
+            frac: (((amount % divisor) << 64) / divisor) as u64,
+        }
+    }
+
+    /// `self · num / den` for `num <= den`, truncated to 64 fractional bits.
+    pub fn mul_ratio(self, num: u64, den: u64) -> Self {
+        debug_assert!(num <= den);
+        let (num, den) = (u128::from(num), u128::from(den));
+        // Neither product can overflow since both factors are below 2^64.
+        let scaled = self.whole % den * num;
+        let whole = self.whole / den * num + scaled / den;
+        let (high, low) = ((scaled % den) << 64, u128::from(self.frac) * num);
+        let frac = high / den + low / den + (high % den + low % den) / den;
+        Self {
+            whole: whole + (frac >> 64),
+            frac: frac as u64,
+        }
+    }
+
+    /// `floor(self · n)`, or `None` if it overflows.
+    pub fn checked_mul_floor(self, n: u64) -> Option<u128> {
+        let n = u128::from(n);
+        self.whole
+            .checked_mul(n)?
+            .checked_add((u128::from(self.frac) * n) >> 64)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        let (frac, carry) = self.frac.overflowing_add(other.frac);
+        match self
+            .whole
+            .checked_add(other.whole)
+            .and_then(|w| w.checked_add(carry.into()))
+        {
+            Some(whole) => Self { whole, frac },
+            None => Self {
+                whole: u128::MAX,
+                frac: u64::MAX,
+            },
+        }
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        let (frac, borrow) = self.frac.overflowing_sub(other.frac);
+        match self
+            .whole
+            .checked_sub(other.whole)
+            .and_then(|w| w.checked_sub(borrow.into()))
+        {
+            Some(whole) => Self { whole, frac },
+            None => Self::default(),
+        }
+    }
+
+    /// Round to a whole amount.
+    pub fn round(self, mode: RoundingMode) -> u128 {
+        const HALF: u64 = 1 << 63;
+        let up = match mode {
+            RoundingMode::Floor => false,
+            RoundingMode::Ceil => self.frac > 0,
+            RoundingMode::HalfEven => {
+                self.frac > HALF || (self.frac == HALF && self.whole % 2 == 1)
+            }
+        };
+        self.whole.saturating_add(up.into())
+    }
+
+    /// `self / 2^k`, truncated to 64 fractional bits.
+    fn shr(self, k: u32) -> Self {
+        let frac = self.frac as u128;
+        match k {
+            0 => self,
+            1..64 => Self {
+                whole: self.whole >> k,
+                frac: ((frac >> k) | ((self.whole & ((1 << k) - 1)) << (64 - k))) as u64,
+            },
+            64..128 => Self {
+                whole: self.whole >> k,
+                frac: (self.whole >> (k - 64)) as u64,
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// A half-life with explicit units, stored in whole seconds.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HalfLife {
+    secs: u64,
+}
+
+impl HalfLife {
+    pub const SECONDS_PER_DAY: u64 = 86_400;
+
+    pub const fn seconds(secs: u64) -> Self {
+        Self { secs }
+    }
+
+    pub const fn minutes(minutes: u64) -> Self {
+        Self::seconds(minutes.saturating_mul(60))
+    }
+
+    pub const fn hours(hours: u64) -> Self {
+        Self::seconds(hours.saturating_mul(3_600))
+    }
+
+    pub const fn days(days: u64) -> Self {
+        Self::seconds(days.saturating_mul(Self::SECONDS_PER_DAY))
+    }
+
+    pub const fn as_secs(self) -> u64 {
+        self.secs
+    }
+}
+
+/// Sub-second precision is truncated. Durations under a second are rejected rather than
+/// truncated to zero, which would vest everything within a second.
+impl TryFrom<Duration> for HalfLife {
+    type Error = ShortHalfLife;
+
+    fn try_from(duration: Duration) -> Result<Self, Self::Error> {
+        match duration.as_secs() {
+            0 => Err(ShortHalfLife(duration)),
+            secs => Ok(Self::seconds(secs)),
+        }
+    }
+}
+
+impl From<HalfLife> for Duration {
+    fn from(half_life: HalfLife) -> Self {
+        Duration::from_secs(half_life.secs)
+    }
+}
+
+/// `a · f` truncated to 64 fractional bits, where `f` is Q64.64 and `f <= 1`.
+fn mul_q64(a: FixedAmount, f: u128) -> FixedAmount {
+    debug_assert!(f <= ONE);
+    let hi = a.whole >> 64;
+    let lo = a.whole & (ONE - 1);
+    let lo_f = lo * f;
+    let frac = (lo_f & (ONE - 1)) + ((a.frac as u128 * f) >> 64);
+    FixedAmount {
+        whole: hi * f + (lo_f >> 64) + (frac >> 64),
+        frac: frac as u64,
+    }
+}
+
+/// `e^{-r}` in Q64.64 for `0 <= r < ln 2`.
+fn exp_neg(r: u128) -> u128 {
+    debug_assert!(r < LN_2);
+    let mut sum = ONE;
+    let mut term = ONE;
+    let mut n = 1;
+    loop {
+        // term <= 1 and r < 1, so the product fits in 128 bits.
+        term = ((term * r) >> 64) / n;
+        if term == 0 {
+            return sum;
+        }
+        if n % 2 == 1 {
+            sum -= term;
+        } else {
+            sum += term;
+        }
+        n += 1;
+    }
+}
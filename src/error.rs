@@ -0,0 +1,327 @@
+use std::fmt;
+use std::time::Duration;
+
+/// An operation was requested at a timestamp earlier than the stream's last update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeRegression {
+    pub at: u64,
+    pub last_update: u64,
+}
+
+impl fmt::Display for TimeRegression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "timestamp {} is earlier than the last update at {}",
+            self.at, self.last_update
+        )
+    }
+}
+
+impl std::error::Error for TimeRegression {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClaimError {
+    /// More was requested than is currently claimable.
+    InsufficientClaimable {
+        requested: u128,
+        claimable: u128,
+    },
+    /// The stream is paused.
+    Paused,
+    TimeRegression(TimeRegression),
+}
+
+impl From<TimeRegression> for ClaimError {
+    fn from(e: TimeRegression) -> Self {
+        Self::TimeRegression(e)
+    }
+}
+
+impl fmt::Display for ClaimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientClaimable {
+                requested,
+                claimable,
+            } => write!(f, "requested {requested} but only {claimable} is claimable"),
+            Self::Paused => write!(f, "stream is paused"),
+            Self::TimeRegression(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ClaimError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitError {
+    /// More was requested than is still vesting.
+    InsufficientStillVesting {
+        requested: u128,
+        still_vesting: u128,
+    },
+    /// The fraction to split off has a zero denominator or exceeds one.
+    InvalidFraction {
+        numerator: u64,
+        denominator: u64,
+    },
+    TimeRegression(TimeRegression),
+}
+
+impl From<TimeRegression> for SplitError {
+    fn from(e: TimeRegression) -> Self {
+        Self::TimeRegression(e)
+    }
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientStillVesting {
+                requested,
+                still_vesting,
+            } => write!(
+                f,
+                "requested {requested} but only {still_vesting} is still vesting"
+            ),
+            Self::InvalidFraction {
+                numerator,
+                denominator,
+            } => write!(f, "cannot split off {numerator}/{denominator}"),
+            Self::TimeRegression(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for SplitError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeError {
+    /// The streams would not vest the same way from the merge on.
+    VestsDifferently,
+    TimeRegression(TimeRegression),
+}
+
+impl From<TimeRegression> for MergeError {
+    fn from(e: TimeRegression) -> Self {
+        Self::TimeRegression(e)
+    }
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::VestsDifferently => write!(f, "streams vest differently"),
+            Self::TimeRegression(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WithdrawError {
+    /// More was requested than is still vesting.
+    InsufficientStillVesting {
+        requested: u128,
+        still_vesting: u128,
+    },
+    /// The stream is paused.
+    Paused,
+    /// The policy would forfeit more than the amount withdrawn.
+    InvalidPenalty(InvalidPenalty),
+    TimeRegression(TimeRegression),
+}
+
+impl From<TimeRegression> for WithdrawError {
+    fn from(e: TimeRegression) -> Self {
+        Self::TimeRegression(e)
+    }
+}
+
+impl fmt::Display for WithdrawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InsufficientStillVesting {
+                requested,
+                still_vesting,
+            } => write!(
+                f,
+                "requested {requested} but only {still_vesting} is still vesting"
+            ),
+            Self::Paused => write!(f, "stream is paused"),
+            Self::InvalidPenalty(e) => e.fmt(f),
+            Self::TimeRegression(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for WithdrawError {}
+
+/// A broken accounting rule in a stream's stored state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// More has been claimed than has vested, so `deposited = claimed + still_vesting + claimable`
+    /// cannot hold for any non-negative `claimable`.
+    ClaimedExceedsVested { claimed: u128, vested: u128 },
+    /// `total_vested` went down since the last update.
+    VestedDecreased { before: u128, after: u128 },
+    /// The vesting principal exceeds what was deposited and not yet claimed.
+    PrincipalExceedsUnclaimed { principal: u128, unclaimed: u128 },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClaimedExceedsVested { claimed, vested } => {
+                write!(f, "claimed {claimed} exceeds vested {vested}")
+            }
+            Self::VestedDecreased { before, after } => {
+                write!(f, "total vested decreased from {before} to {after}")
+            }
+            Self::PrincipalExceedsUnclaimed {
+                principal,
+                unclaimed,
+            } => write!(f, "principal {principal} exceeds unclaimed {unclaimed}"),
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+/// Every invariant that failed in a single check.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InvariantReport {
+    pub violations: Vec<InvariantViolation>,
+}
+
+impl fmt::Display for InvariantReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} invariant(s) violated", self.violations.len())?;
+        for (i, v) in self.violations.iter().enumerate() {
+            write!(f, "{} {v}", if i == 0 { ":" } else { ";" })?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvariantReport {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InvariantCheckError {
+    Violated(InvariantReport),
+    TimeRegression(TimeRegression),
+}
+
+impl From<InvariantReport> for InvariantCheckError {
+    fn from(e: InvariantReport) -> Self {
+        Self::Violated(e)
+    }
+}
+
+impl From<TimeRegression> for InvariantCheckError {
+    fn from(e: TimeRegression) -> Self {
+        Self::TimeRegression(e)
+    }
+}
+
+impl fmt::Display for InvariantCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Violated(e) => e.fmt(f),
+            Self::TimeRegression(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for InvariantCheckError {}
+
+/// Errors surfaced by the `checked_*` APIs instead of saturating.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenStreamError {
+    /// A cumulative total would exceed `u128::MAX`.
+    Overflow,
+    /// The stream is paused.
+    Paused,
+    TimeRegression(TimeRegression),
+    InvariantViolation(InvariantViolation),
+}
+
+impl From<TimeRegression> for TokenStreamError {
+    fn from(e: TimeRegression) -> Self {
+        Self::TimeRegression(e)
+    }
+}
+
+impl From<InvariantViolation> for TokenStreamError {
+    fn from(e: InvariantViolation) -> Self {
+        Self::InvariantViolation(e)
+    }
+}
+
+impl fmt::Display for TokenStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "arithmetic overflow"),
+            Self::Paused => write!(f, "stream is paused"),
+            Self::TimeRegression(e) => e.fmt(f),
+            Self::InvariantViolation(e) => write!(f, "invariant violated: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TokenStreamError {}
+
+/// A penalty above 100%, e.g. in a deserialized [`PenaltyPolicy`](crate::PenaltyPolicy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidPenalty {
+    pub penalty_bps: u64,
+}
+
+impl fmt::Display for InvalidPenalty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "penalty of {} basis points is above 100%",
+            self.penalty_bps
+        )
+    }
+}
+
+impl std::error::Error for InvalidPenalty {}
+
+/// A half-life given as a [`Duration`] is shorter than one second.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShortHalfLife(pub Duration);
+
+impl fmt::Display for ShortHalfLife {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "half-life of {:?} is shorter than one second", self.0)
+    }
+}
+
+impl std::error::Error for ShortHalfLife {}
+
+/// Rewards cannot be distributed to a pool without shares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoShareholders;
+
+impl fmt::Display for NoShareholders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pool has no shares to distribute to")
+    }
+}
+
+impl std::error::Error for NoShareholders {}
+
+/// A deposit would need a new rate class, but a multi-rate stream already has its maximum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooManyRates;
+
+impl fmt::Display for TooManyRates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stream already has the maximum number of decay rates")
+    }
+}
+
+impl std::error::Error for TooManyRates {}
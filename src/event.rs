@@ -0,0 +1,112 @@
+//! Auditable history of a [`TokenStream`](crate::TokenStream).
+//!
+//! A stream built with [`TokenStream::with_event_log`](crate::TokenStream::with_event_log)
+//! appends an [`Event`] for every operation, recording its timestamp and the resulting state.
+//! [`TokenStream::replay`](crate::TokenStream::replay) rebuilds the stream from the log alone and
+//! verifies every recorded state along the way.
+
+use std::fmt;
+
+use crate::{HalfLife, PenaltyPolicy, Snapshot, SnapshotError};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EventKind {
+    /// Logging started. The recorded state is where replay begins.
+    Open,
+    Deposit {
+        amount: u128,
+    },
+    Claim {
+        amount: u128,
+    },
+    Settle,
+    SetHalfLife {
+        half_life: HalfLife,
+    },
+    /// A half-life change that takes effect at `at`.
+    ScheduleHalfLife {
+        at: u64,
+        half_life: HalfLife,
+    },
+    Pause,
+    Resume,
+    /// Still-vesting principal taken back, including by a revocation.
+    Clawback {
+        amount: u128,
+    },
+    /// Still-vesting principal moved out into a new stream.
+    Split {
+        amount: u128,
+    },
+    /// Still-vesting principal withdrawn early, part of it forfeited.
+    EarlyWithdraw {
+        amount: u128,
+        policy: PenaltyPolicy,
+    },
+    /// Another stream, in the state it was merged in.
+    Merge {
+        other: Box<Snapshot>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Event {
+    pub timestamp: u64,
+    pub kind: EventKind,
+    /// The stream's state right after the operation.
+    pub state: Snapshot,
+}
+
+/// Append-only list of events.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventLog {
+    events: Vec<Event>,
+}
+
+impl EventLog {
+    pub(crate) fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The log does not start with [`EventKind::Open`].
+    MissingOpen,
+    /// The opening state could not be restored.
+    Snapshot(SnapshotError),
+    /// The event at `index` could not be applied, e.g. its timestamp goes backwards.
+    Rejected { index: usize },
+    /// Applying the event at `index` did not reproduce its recorded state.
+    Mismatch { index: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingOpen => write!(f, "event log does not start with an open event"),
+            Self::Snapshot(e) => write!(f, "cannot restore opening state: {e}"),
+            Self::Rejected { index } => write!(f, "event {index} cannot be applied"),
+            Self::Mismatch { index } => {
+                write!(f, "event {index} does not match its recorded state")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
@@ -0,0 +1,69 @@
+use crate::{Event, EventLog, Snapshot, TokenStream};
+
+/// Point-in-time queries over a stream's past, answered in O(log n).
+///
+/// Keeps the state after every operation as a checkpoint. A query at `t` finds the last
+/// checkpoint at or before `t` and decays its curve forward to `t`, which is exact because
+/// nothing changed the curve in between.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+    checkpoints: Vec<Snapshot>,
+}
+
+impl History {
+    pub fn from_events(events: &[Event]) -> Self {
+        let mut history = Self::default();
+        for event in events {
+            history.push(event.state.clone());
+        }
+        history
+    }
+
+    /// Append the state after an operation. Checkpoints must not go back in time.
+    pub fn push(&mut self, checkpoint: Snapshot) {
+        if let Some(last) = self.checkpoints.last() {
+            assert!(
+                checkpoint.last_update_timestamp >= last.last_update_timestamp,
+                "checkpoints must be in time order"
+            );
+        }
+        self.checkpoints.push(checkpoint);
+    }
+
+    pub fn checkpoints(&self) -> &[Snapshot] {
+        &self.checkpoints
+    }
+
+    /// The state in effect at `t`, or `None` before the first checkpoint.
+    pub fn checkpoint_at(&self, t: u64) -> Option<&Snapshot> {
+        let after = self
+            .checkpoints
+            .partition_point(|c| c.last_update_timestamp <= t);
+        after.checked_sub(1).map(|i| &self.checkpoints[i])
+    }
+
+    /// Total vested as of `t`.
+    pub fn vested_at(&self, t: u64) -> Option<u128> {
+        self.stream_at(t)?.total_vested_at(t).ok()
+    }
+
+    /// Total claimed as of `t`.
+    pub fn claimed_at(&self, t: u64) -> Option<u128> {
+        Some(self.checkpoint_at(t)?.total_claimed)
+    }
+
+    /// Amount still vesting as of `t`.
+    pub fn still_vesting_at(&self, t: u64) -> Option<u128> {
+        self.stream_at(t)?.balance_still_vesting_at(t).ok()
+    }
+
+    fn stream_at(&self, t: u64) -> Option<TokenStream> {
+        TokenStream::from_snapshot(self.checkpoint_at(t)?.clone()).ok()
+    }
+}
+
+impl From<&EventLog> for History {
+    fn from(log: &EventLog) -> Self {
+        Self::from_events(log.events())
+    }
+}
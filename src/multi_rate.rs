@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use crate::{Clock, DecayRate, RoundingMode, ThreadClock, TokenStream, TooManyRates};
+
+/// One account's deposits at several decay rates.
+///
+/// Exponentials with different rates do not merge, so deposits are grouped by rate into one
+/// stream per rate class, and balances are summed across them. At most `max_rates` classes are
+/// open at once; a class whose deposits have all been claimed is closed to make room for a new
+/// one.
+pub struct MultiRateStream<C = ThreadClock> {
+    max_rates: usize,
+    rounding: RoundingMode,
+    buckets: BTreeMap<DecayRate, TokenStream<C>>,
+    // Deposited, and so also claimed, by classes since closed.
+    closed: u128,
+    clock: C,
+}
+
+impl MultiRateStream {
+    pub fn new(max_rates: usize) -> Self {
+        Self {
+            max_rates,
+            rounding: RoundingMode::default(),
+            buckets: BTreeMap::new(),
+            closed: 0,
+            clock: ThreadClock,
+        }
+    }
+}
+
+impl<C: Clock + Clone> MultiRateStream<C> {
+    /// Replace the shared time source. Must be called before any deposit.
+    pub fn with_clock<D: Clock + Clone>(self, clock: D) -> MultiRateStream<D> {
+        assert!(self.buckets.is_empty(), "stream already has deposits");
+        MultiRateStream {
+            max_rates: self.max_rates,
+            rounding: self.rounding,
+            buckets: BTreeMap::new(),
+            closed: self.closed,
+            clock,
+        }
+    }
+
+    /// Rounding used by every rate class. Must be called before any deposit.
+    pub fn with_rounding(self, rounding: RoundingMode) -> Self {
+        assert!(self.buckets.is_empty(), "stream already has deposits");
+        Self { rounding, ..self }
+    }
+
+    pub fn max_rates(&self) -> usize {
+        self.max_rates
+    }
+
+    /// Deposit `amount` vesting at `rate`, opening its class if needed.
+    pub fn deposit(&mut self, rate: DecayRate, amount: u128) -> Result<(), TooManyRates> {
+        if !self.buckets.contains_key(&rate) {
+            if self.buckets.len() >= self.max_rates {
+                self.close_drained();
+            }
+            if self.buckets.len() >= self.max_rates {
+                return Err(TooManyRates);
+            }
+            let stream = TokenStream::from_decay_rate(rate)
+                .with_rounding(self.rounding)
+                .with_clock(self.clock.clone());
+            self.buckets.insert(rate, stream);
+        }
+        self.buckets
+            .get_mut(&rate)
+            .expect("class just opened")
+            .deposit(amount);
+        Ok(())
+    }
+
+    /// Claim everything claimable across all rate classes.
+    pub fn claim(&mut self) -> u128 {
+        self.buckets
+            .values_mut()
+            .fold(0, |acc, s| acc.saturating_add(s.claim()))
+    }
+
+    /// The stream of one rate class, if open.
+    pub fn get(&self, rate: DecayRate) -> Option<&TokenStream<C>> {
+        self.buckets.get(&rate)
+    }
+
+    /// Open rate classes, slowest first.
+    pub fn iter(&self) -> impl Iterator<Item = (&DecayRate, &TokenStream<C>)> {
+        self.buckets.iter()
+    }
+
+    /// Number of open rate classes.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    pub fn balance_claimable(&self) -> u128 {
+        self.sum(TokenStream::balance_claimable)
+    }
+
+    pub fn balance_still_vesting(&self) -> u128 {
+        self.sum(TokenStream::balance_still_vesting)
+    }
+
+    pub fn total_deposited(&self) -> u128 {
+        self.sum(TokenStream::total_deposited)
+            .saturating_add(self.closed)
+    }
+
+    pub fn total_vested(&self) -> u128 {
+        self.sum(TokenStream::total_vested)
+            .saturating_add(self.closed)
+    }
+
+    pub fn total_claimed(&self) -> u128 {
+        self.sum(TokenStream::total_claimed)
+            .saturating_add(self.closed)
+    }
+
+    /// Close every class with nothing left to vest or claim.
+    fn close_drained(&mut self) {
+        let mut closed = self.closed;
+        self.buckets.retain(|_, s| {
+            let drained = s.unclaimed_total() == 0;
+            if drained {
+                closed = closed.saturating_add(s.total_deposited());
+            }
+            !drained
+        });
+        self.closed = closed;
+    }
+
+    fn sum(&self, f: impl Fn(&TokenStream<C>) -> u128) -> u128 {
+        self.buckets
+            .values()
+            .fold(0, |acc, s| acc.saturating_add(f(s)))
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{Clock, NoShareholders, RewardPool};
+
+/// Basis points in one whole.
+const BPS: u64 = 10_000;
+
+/// What an early withdrawal costs, and where the forfeited part should go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "RawPenaltyPolicy")
+)]
+pub struct PenaltyPolicy {
+    penalty_bps: u64,
+    destination: ForfeitDestination,
+}
+
+/// A policy as stored, before the penalty is checked.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawPenaltyPolicy {
+    penalty_bps: u64,
+    destination: ForfeitDestination,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<RawPenaltyPolicy> for PenaltyPolicy {
+    type Error = crate::InvalidPenalty;
+
+    fn try_from(raw: RawPenaltyPolicy) -> Result<Self, Self::Error> {
+        if raw.penalty_bps > BPS {
+            return Err(crate::InvalidPenalty {
+                penalty_bps: raw.penalty_bps,
+            });
+        }
+        Ok(Self::new(raw.penalty_bps, raw.destination))
+    }
+}
+
+/// Where forfeited tokens go. A stream only records the choice; [`PenaltyPolicy::route`] carries
+/// it out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ForfeitDestination {
+    Burn,
+    Treasury,
+    /// Redistributed to the other participants of a pool.
+    Pool,
+}
+
+impl PenaltyPolicy {
+    /// Forfeit `penalty_bps` basis points of every early withdrawal, at most the whole of it.
+    pub fn new(penalty_bps: u64, destination: ForfeitDestination) -> Self {
+        assert!(penalty_bps <= BPS, "penalty above 100%");
+        Self {
+            penalty_bps,
+            destination,
+        }
+    }
+
+    pub fn penalty_bps(&self) -> u64 {
+        self.penalty_bps
+    }
+
+    pub fn destination(&self) -> ForfeitDestination {
+        self.destination
+    }
+
+    /// The part of `amount` forfeited, rounded down.
+    pub fn forfeit(&self, amount: u128) -> u128 {
+        let bps = u128::from(BPS);
+        amount / bps * u128::from(self.penalty_bps)
+            + amount % bps * u128::from(self.penalty_bps) / bps
+    }
+
+    /// Send `forfeited` to this policy's destination. Pool forfeits are distributed through
+    /// `pool` to its holders, which fails without a pool or shares; burned and treasury forfeits
+    /// are left for the caller to burn or transfer, since streams hold no tokens themselves and
+    /// `pool` is not needed for them. Nothing changes on error.
+    pub fn route<K: Ord, C: Clock + Clone>(
+        &self,
+        forfeited: u128,
+        pool: Option<&mut RewardPool<K, C>>,
+    ) -> Result<Routed, NoShareholders> {
+        match self.destination {
+            ForfeitDestination::Burn => Ok(Routed::Burn(forfeited)),
+            ForfeitDestination::Treasury => Ok(Routed::Treasury(forfeited)),
+            ForfeitDestination::Pool => {
+                let pool = pool.ok_or(NoShareholders)?;
+                pool.distribute(forfeited)?;
+                Ok(Routed::Pool(forfeited))
+            }
+        }
+    }
+}
+
+/// Where [`PenaltyPolicy::route`] sent a forfeited amount.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Routed {
+    /// Still to be burned by the caller.
+    Burn(u128),
+    /// Still to be paid to the treasury by the caller.
+    Treasury(u128),
+    /// Already distributed to the pool's holders.
+    Pool(u128),
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use crate::{Clock, DecayRate, FixedAmount, HalfLife, NoShareholders, ThreadClock, TokenStream};
+
+/// Rewards distributed pro-rata to share holders through one shared stream.
+///
+/// Every holder's rewards decay at the same rate, so instead of depositing into each holder's
+/// stream, [`RewardPool::distribute`] updates two per-share accumulators in O(1):
+/// * everything ever distributed per share, and
+/// * the same sum with each distribution decayed since it was made. By the "snapshot, then add"
+///   rule this is itself a single exponential.
+///
+/// A holder's stream is brought up to date lazily from how much both accumulators moved since it
+/// was last touched, which gives the same result as depositing into it on every distribution.
+/// Per-holder amounts round down, so a little dust can remain in the pool.
+///
+/// The rate is fixed for the life of the pool.
+pub struct RewardPool<K, C = ThreadClock> {
+    // Aggregate of everything distributed. Also provides the clock.
+    pool: TokenStream<C>,
+    total_shares: u64,
+    total_claimed: u128,
+
+    deposited_per_share: FixedAmount,
+    vesting_per_share: FixedAmount, // As of `index_timestamp`
+    index_timestamp: u64,
+    last_update_timestamp: u64,
+
+    holders: BTreeMap<K, Holder<C>>,
+}
+
+struct Holder<C> {
+    shares: u64,
+    stream: TokenStream<C>,
+    // The pool's accumulators when `stream` was last brought up to date.
+    deposited_per_share: FixedAmount,
+    vesting_per_share: FixedAmount,
+}
+
+impl<K: Ord> RewardPool<K> {
+    pub fn new(decay_rate_per_second: f64) -> Self {
+        Self::from_decay_rate(DecayRate::from_f64(decay_rate_per_second))
+    }
+
+    pub fn new_from_half_life(half_life: HalfLife) -> Self {
+        Self::from_decay_rate(DecayRate::from_half_life(half_life))
+    }
+
+    pub fn from_decay_rate(decay_rate_per_second: DecayRate) -> Self {
+        Self {
+            pool: TokenStream::from_decay_rate(decay_rate_per_second),
+            total_shares: 0,
+            total_claimed: 0,
+            deposited_per_share: FixedAmount::default(),
+            vesting_per_share: FixedAmount::default(),
+            index_timestamp: 0,
+            last_update_timestamp: 0,
+            holders: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, C: Clock + Clone> RewardPool<K, C> {
+    /// Replace the shared time source. Must be called before any holder is added.
+    pub fn with_clock<D: Clock + Clone>(self, clock: D) -> RewardPool<K, D> {
+        assert!(self.holders.is_empty(), "pool already has holders");
+        RewardPool {
+            pool: self.pool.with_clock(clock),
+            total_shares: self.total_shares,
+            total_claimed: self.total_claimed,
+            deposited_per_share: self.deposited_per_share,
+            vesting_per_share: self.vesting_per_share,
+            index_timestamp: self.index_timestamp,
+            last_update_timestamp: self.last_update_timestamp,
+            holders: BTreeMap::new(),
+        }
+    }
+
+    /// Set `account`'s shares. Rewards distributed so far keep vesting for the account either way.
+    pub fn set_shares(&mut self, account: K, shares: u64) {
+        let t = self.touch();
+        let deposited_per_share = self.deposited_per_share;
+        let vesting_per_share = self.vesting_per_share_now(t);
+        let holder = self.holders.entry(account).or_insert_with(|| {
+            let mut stream = TokenStream::from_decay_rate(self.pool.decay_rate())
+                .with_clock(self.pool.clock().clone());
+            stream.accrue_at(t, 0, 0);
+            Holder {
+                shares: 0,
+                stream,
+                deposited_per_share,
+                vesting_per_share,
+            }
+        });
+        Self::accrue(holder, t, deposited_per_share, vesting_per_share);
+        self.total_shares = (self.total_shares - holder.shares)
+            .checked_add(shares)
+            .expect("total shares overflow");
+        holder.shares = shares;
+    }
+
+    pub fn shares(&self, account: &K) -> u64 {
+        self.holders.get(account).map_or(0, |h| h.shares)
+    }
+
+    pub fn total_shares(&self) -> u64 {
+        self.total_shares
+    }
+
+    /// Split `amount` across all current shares in O(1). Each holder's part starts vesting now.
+    pub fn distribute(&mut self, amount: u128) -> Result<(), NoShareholders> {
+        if self.total_shares == 0 {
+            return Err(NoShareholders);
+        }
+        let t = self.touch();
+        let per_share = FixedAmount::from_ratio(amount, self.total_shares);
+        self.vesting_per_share = self.vesting_per_share_now(t).saturating_add(per_share);
+        self.index_timestamp = t;
+        self.deposited_per_share = self.deposited_per_share.saturating_add(per_share);
+        self.pool.deposit_unchecked(t, amount);
+        Ok(())
+    }
+
+    /// Claim everything claimable for `account`; unknown accounts claim nothing.
+    pub fn claim(&mut self, account: &K) -> u128 {
+        let t = self.touch();
+        let vesting_per_share = self.vesting_per_share_now(t);
+        let Some(holder) = self.holders.get_mut(account) else {
+            return 0;
+        };
+        Self::accrue(holder, t, self.deposited_per_share, vesting_per_share);
+        let claimed = holder.stream.claim_unchecked(t);
+        self.total_claimed = self.total_claimed.saturating_add(claimed);
+        claimed
+    }
+
+    pub fn balance_claimable(&self, account: &K) -> u128 {
+        let t = self.now();
+        self.holders.get(account).map_or(0, |h| {
+            let (deposited, still_vesting) = self.pending(h, t);
+            (h.stream.claimable_at(t) + deposited).saturating_sub(still_vesting)
+        })
+    }
+
+    pub fn balance_still_vesting(&self, account: &K) -> u128 {
+        let t = self.now();
+        self.holders.get(account).map_or(0, |h| {
+            let (_, still_vesting) = self.pending(h, t);
+            h.stream.principal_at(t) + still_vesting
+        })
+    }
+
+    /// Total distributed since inception.
+    pub fn total_distributed(&self) -> u128 {
+        self.pool.total_deposited()
+    }
+
+    /// Total vested across the pool, before per-holder rounding.
+    pub fn total_vested(&self) -> u128 {
+        self.pool.vested_at(self.now())
+    }
+
+    /// Total claimed by all holders.
+    pub fn total_claimed(&self) -> u128 {
+        self.total_claimed
+    }
+
+    fn now(&self) -> u64 {
+        self.pool.clock().now().max(self.last_update_timestamp)
+    }
+
+    fn touch(&mut self) -> u64 {
+        self.last_update_timestamp = self.now();
+        self.last_update_timestamp
+    }
+
+    fn vesting_per_share_now(&self, t: u64) -> FixedAmount {
+        self.pool
+            .decay_rate()
+            .decay_fixed(self.vesting_per_share, t - self.index_timestamp)
+    }
+
+    /// Rewards distributed to `holder` since it was last brought up to date, as
+    /// `(deposited, still_vesting)` at `t`.
+    fn pending(&self, holder: &Holder<C>, t: u64) -> (u128, u128) {
+        Self::pending_for(
+            holder,
+            t,
+            self.deposited_per_share,
+            self.vesting_per_share_now(t),
+        )
+    }
+
+    fn pending_for(
+        holder: &Holder<C>,
+        t: u64,
+        deposited_per_share: FixedAmount,
+        vesting_per_share: FixedAmount,
+    ) -> (u128, u128) {
+        let since = t - holder.stream.last_update_timestamp;
+        let held = holder
+            .stream
+            .decay_rate()
+            .decay_fixed(holder.vesting_per_share, since);
+        let deposited = deposited_per_share
+            .saturating_sub(holder.deposited_per_share)
+            .checked_mul_floor(holder.shares)
+            .unwrap_or(u128::MAX);
+        let still_vesting = vesting_per_share
+            .saturating_sub(held)
+            .checked_mul_floor(holder.shares)
+            .unwrap_or(u128::MAX);
+        (deposited, still_vesting.min(deposited))
+    }
+
+    fn accrue(
+        holder: &mut Holder<C>,
+        t: u64,
+        deposited_per_share: FixedAmount,
+        vesting_per_share: FixedAmount,
+    ) {
+        let (deposited, still_vesting) =
+            Self::pending_for(holder, t, deposited_per_share, vesting_per_share);
+        holder.stream.accrue_at(t, deposited, still_vesting);
+        holder.deposited_per_share = deposited_per_share;
+        holder.vesting_per_share = vesting_per_share;
+    }
+}
@@ -0,0 +1,91 @@
+use std::iter::Peekable;
+use std::vec;
+
+use crate::{EventKind, TimeRegression, TokenStream};
+
+/// Forecast of a stream sampled at fixed intervals, as `(t, total_vested, still_vesting)`.
+///
+/// Built by [`TokenStream::project`] and [`TokenStream::project_with`]. Samples are exactly what
+/// the stream itself would report at each `t`. Sampling starts no earlier than the stream's
+/// last update, and a `step` of zero yields no samples.
+pub struct Projection {
+    stream: TokenStream,
+    plan: Peekable<vec::IntoIter<(u64, EventKind)>>,
+    next: Option<u64>,
+    to: u64,
+    step: u64,
+}
+
+impl Projection {
+    pub(crate) fn new(
+        stream: TokenStream,
+        from: u64,
+        to: u64,
+        step: u64,
+        mut plan: Vec<(u64, EventKind)>,
+    ) -> Self {
+        plan.sort_by_key(|&(t, _)| t);
+        Self {
+            next: (step > 0).then(|| from.max(stream.last_update_timestamp)),
+            stream,
+            plan: plan.into_iter().peekable(),
+            to,
+            step,
+        }
+    }
+
+    fn apply(&mut self, t: u64, kind: EventKind) -> Result<(), TimeRegression> {
+        let b = &mut self.stream;
+        match kind {
+            EventKind::Open | EventKind::Settle => {}
+            EventKind::Deposit { amount } => b.deposit_at(t, amount)?,
+            EventKind::Claim { amount } => {
+                let amount = amount.min(b.balance_claimable_at(t)?);
+                // Refused while paused.
+                let _ = b.claim_amount_at(t, amount);
+            }
+            EventKind::SetHalfLife { half_life } => b.set_half_life_at(t, half_life)?,
+            EventKind::ScheduleHalfLife { at, half_life } => {
+                b.schedule_half_life_at(t, at, half_life)?
+            }
+            EventKind::Clawback { amount } => {
+                b.clawback_at(t, amount)?;
+            }
+            EventKind::Split { amount } => {
+                let amount = amount.min(b.balance_still_vesting_at(t)?);
+                b.split_at(t, amount)
+                    .expect("split within the balance still vesting");
+            }
+            EventKind::EarlyWithdraw { amount, policy } => {
+                let amount = amount.min(b.balance_still_vesting_at(t)?);
+                // Refused while paused, like claims.
+                let _ = b.early_withdraw_at(t, amount, policy);
+            }
+            EventKind::Merge { other } => {
+                // Streams that vest differently are not merged.
+                if let Ok(other) = TokenStream::from_snapshot(*other) {
+                    let _ = b.merge_at(t, other);
+                }
+            }
+            EventKind::Pause => b.pause_at(t)?,
+            EventKind::Resume => b.resume_at(t)?,
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Projection {
+    type Item = (u64, u128, u128);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let t = self.next.filter(|&t| t <= self.to)?;
+        self.next = t.checked_add(self.step);
+        while let Some((at, kind)) = self.plan.next_if(|&(at, _)| at <= t) {
+            // Operations planned before the stream's last update cannot happen.
+            let _ = self.apply(at, kind);
+        }
+        let vested = self.stream.total_vested_at(t).ok()?;
+        let still_vesting = self.stream.balance_still_vesting_at(t).ok()?;
+        Some((t, vested, still_vesting))
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+
+use crate::{Clock, DecayRate, HalfLife, RoundingMode, ThreadClock, TokenStream};
+
+/// Many streams keyed by account, sharing one clock and one rate configuration.
+///
+/// Accounts are kept in key order, so iteration and aggregate totals are deterministic.
+pub struct StreamRegistry<K, C = ThreadClock> {
+    decay_rate_per_second: DecayRate,
+    rounding: RoundingMode,
+    streams: BTreeMap<K, TokenStream<C>>,
+    clock: C,
+}
+
+impl<K: Ord> StreamRegistry<K> {
+    pub fn new(decay_rate_per_second: f64) -> Self {
+        Self::from_decay_rate(DecayRate::from_f64(decay_rate_per_second))
+    }
+
+    pub fn new_from_half_life(half_life: HalfLife) -> Self {
+        Self::from_decay_rate(DecayRate::from_half_life(half_life))
+    }
+
+    pub fn from_decay_rate(decay_rate_per_second: DecayRate) -> Self {
+        Self {
+            decay_rate_per_second,
+            rounding: RoundingMode::default(),
+            streams: BTreeMap::new(),
+            clock: ThreadClock,
+        }
+    }
+}
+
+impl<K: Ord, C: Clock + Clone> StreamRegistry<K, C> {
+    /// Replace the shared time source. Must be called before any account is added.
+    pub fn with_clock<D: Clock + Clone>(self, clock: D) -> StreamRegistry<K, D> {
+        assert!(self.streams.is_empty(), "registry already has accounts");
+        StreamRegistry {
+            decay_rate_per_second: self.decay_rate_per_second,
+            rounding: self.rounding,
+            streams: BTreeMap::new(),
+            clock,
+        }
+    }
+
+    /// Rounding used by every account. Must be called before any account is added.
+    pub fn with_rounding(self, rounding: RoundingMode) -> Self {
+        assert!(self.streams.is_empty(), "registry already has accounts");
+        Self { rounding, ..self }
+    }
+
+    pub fn decay_rate(&self) -> DecayRate {
+        self.decay_rate_per_second
+    }
+
+    /// Change the half-life of every account, and of accounts added later.
+    pub fn set_half_life(&mut self, half_life: HalfLife) {
+        self.decay_rate_per_second = DecayRate::from_half_life(half_life);
+        for stream in self.streams.values_mut() {
+            stream.set_half_life(half_life);
+        }
+    }
+
+    /// Deposit `amount` into `account`'s stream, opening it if needed.
+    pub fn deposit(&mut self, account: K, amount: u128) {
+        let (rate, rounding, clock) = (self.decay_rate_per_second, self.rounding, &self.clock);
+        self.streams
+            .entry(account)
+            .or_insert_with(|| {
+                TokenStream::from_decay_rate(rate)
+                    .with_rounding(rounding)
+                    .with_clock(clock.clone())
+            })
+            .deposit(amount);
+    }
+
+    /// Claim everything claimable for `account`; unknown accounts claim nothing.
+    pub fn claim(&mut self, account: &K) -> u128 {
+        self.streams.get_mut(account).map_or(0, TokenStream::claim)
+    }
+
+    pub fn get(&self, account: &K) -> Option<&TokenStream<C>> {
+        self.streams.get(account)
+    }
+
+    pub fn get_mut(&mut self, account: &K) -> Option<&mut TokenStream<C>> {
+        self.streams.get_mut(account)
+    }
+
+    /// Close `account`, handing back its stream.
+    pub fn remove(&mut self, account: &K) -> Option<TokenStream<C>> {
+        self.streams.remove(account)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &TokenStream<C>)> {
+        self.streams.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    pub fn balance_claimable(&self, account: &K) -> u128 {
+        self.streams
+            .get(account)
+            .map_or(0, TokenStream::balance_claimable)
+    }
+
+    pub fn balance_still_vesting(&self, account: &K) -> u128 {
+        self.streams
+            .get(account)
+            .map_or(0, TokenStream::balance_still_vesting)
+    }
+
+    /// Sum of [`TokenStream::total_deposited`] across all accounts.
+    pub fn total_deposited(&self) -> u128 {
+        self.sum(TokenStream::total_deposited)
+    }
+
+    /// Sum of [`TokenStream::total_vested`] across all accounts.
+    pub fn total_vested(&self) -> u128 {
+        self.sum(TokenStream::total_vested)
+    }
+
+    /// Sum of [`TokenStream::total_claimed`] across all accounts.
+    pub fn total_claimed(&self) -> u128 {
+        self.sum(TokenStream::total_claimed)
+    }
+
+    /// Sum of [`TokenStream::balance_claimable`] across all accounts.
+    pub fn total_claimable(&self) -> u128 {
+        self.sum(TokenStream::balance_claimable)
+    }
+
+    /// Sum of [`TokenStream::balance_still_vesting`] across all accounts.
+    pub fn total_still_vesting(&self) -> u128 {
+        self.sum(TokenStream::balance_still_vesting)
+    }
+
+    fn sum(&self, f: impl Fn(&TokenStream<C>) -> u128) -> u128 {
+        self.streams
+            .values()
+            .fold(0, |acc, s| acc.saturating_add(f(s)))
+    }
+}
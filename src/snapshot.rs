@@ -0,0 +1,340 @@
+//! Versioned persistence for [`TokenStream`](crate::TokenStream).
+//!
+//! A [`Snapshot`] carries every accounting field plus a format version. Snapshots written by
+//! older versions of this crate are brought up to date by [`Snapshot::migrate`] before a stream
+//! is rebuilt from them, both for serde (behind the `serde` feature) and for the compact binary
+//! encoding, which is a sequence of LEB128 varints starting with the version.
+
+use std::fmt;
+
+use crate::{Curve, DecayRate, ExponentialCurve, HalfLife, LinearCurve, RoundingMode, StepCurve};
+
+/// The format version written by this build.
+///
+/// * 1: accounting totals and the last settled principal.
+/// * 2: adds the origin of the decay curve, which settling no longer moves.
+/// * 3: adds the rounding mode.
+/// * 4: adds the vesting curve.
+/// * 5: adds the cliff.
+/// * 6: adds tranche mode and the locked tranches.
+/// * 7: adds scheduled half-life changes.
+/// * 8: adds the pause state.
+/// * 9: records whether the cliff has started, which the totals cannot tell once everything
+///   deposited has been taken back.
+pub const SNAPSHOT_VERSION: u16 = 9;
+
+/// The persisted state of a stream, independent of its clock.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    pub version: u16,
+    /// The exponential decay rate, or zero for other curves.
+    pub decay_rate: DecayRate,
+    pub total_deposited: u128,
+    pub total_claimed: u128,
+    pub last_update_principal: u128,
+    pub last_update_timestamp: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub origin_principal: u128,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub origin_timestamp: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rounding: RoundingMode,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub curve: Curve,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cliff: u64,
+    /// When the cliff ends, once the first deposit has started it.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cliff_end: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tranche_lock: u64,
+    /// Locked amounts by unlock time, in unlock order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tranches: Vec<(u64, u128)>,
+    /// Half-life changes by the time they take effect, in time order.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rate_schedule: Vec<(u64, HalfLife)>,
+    /// When the stream was paused, if it still is.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub paused_at: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Written by a newer build, or not a snapshot at all.
+    UnsupportedVersion(u16),
+    /// The encoding ended in the middle of a field.
+    Truncated,
+    /// A varint does not fit its field.
+    Overflow,
+    /// Bytes remain after the last field.
+    TrailingBytes,
+    /// A field holds a value outside its domain.
+    InvalidValue,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {v}"),
+            Self::Truncated => write!(f, "snapshot encoding is truncated"),
+            Self::Overflow => write!(f, "snapshot field does not fit its type"),
+            Self::TrailingBytes => write!(f, "unexpected bytes after snapshot"),
+            Self::InvalidValue => write!(f, "snapshot field holds an invalid value"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl Snapshot {
+    /// Bring a snapshot written by an older build up to [`SNAPSHOT_VERSION`].
+    pub fn migrate(self) -> Result<Self, SnapshotError> {
+        match self.version {
+            1 => Self {
+                version: 2,
+                origin_principal: self.last_update_principal,
+                origin_timestamp: self.last_update_timestamp,
+                ..self
+            }
+            .migrate(),
+            2 => Self {
+                version: 3,
+                rounding: RoundingMode::Floor,
+                ..self
+            }
+            .migrate(),
+            3 => Self {
+                version: 4,
+                curve: ExponentialCurve(self.decay_rate).into(),
+                ..self
+            }
+            .migrate(),
+            4 => Self {
+                version: 5,
+                cliff: 0,
+                cliff_end: None,
+                ..self
+            }
+            .migrate(),
+            5 => Self {
+                version: 6,
+                tranche_lock: 0,
+                tranches: Vec::new(),
+                ..self
+            }
+            .migrate(),
+            6 => Self {
+                version: 7,
+                rate_schedule: Vec::new(),
+                ..self
+            }
+            .migrate(),
+            7 => Self {
+                version: 8,
+                paused_at: None,
+                ..self
+            }
+            .migrate(),
+            // Before the first deposit the end was stored as zero.
+            8 => Self {
+                version: 9,
+                cliff_end: self
+                    .cliff_end
+                    .filter(|&end| self.total_deposited > 0 || end > 0),
+                ..self
+            }
+            .migrate(),
+            SNAPSHOT_VERSION => Ok(self),
+            v => Err(SnapshotError::UnsupportedVersion(v)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, self.version.into());
+        write_varint(&mut out, self.decay_rate.to_bits());
+        write_varint(&mut out, self.total_deposited);
+        write_varint(&mut out, self.total_claimed);
+        write_varint(&mut out, self.last_update_principal);
+        write_varint(&mut out, self.last_update_timestamp.into());
+        write_varint(&mut out, self.origin_principal);
+        write_varint(&mut out, self.origin_timestamp.into());
+        write_varint(&mut out, rounding_code(self.rounding));
+        // An exponential curve's rate is already written above.
+        match self.curve {
+            Curve::Exponential(_) => write_varint(&mut out, 0),
+            Curve::Linear(c) => {
+                write_varint(&mut out, 1);
+                write_varint(&mut out, c.cliff().into());
+                write_varint(&mut out, c.duration().into());
+            }
+            Curve::Step(c) => {
+                write_varint(&mut out, 2);
+                write_varint(&mut out, c.period().into());
+                write_varint(&mut out, c.steps().into());
+            }
+        }
+        write_varint(&mut out, self.cliff.into());
+        // Optional times are written as zero for `None`, otherwise one past the time.
+        write_varint(&mut out, self.cliff_end.map_or(0, |t| u128::from(t) + 1));
+        write_varint(&mut out, self.tranche_lock.into());
+        write_varint(&mut out, self.tranches.len() as u128);
+        for &(unlock, amount) in &self.tranches {
+            write_varint(&mut out, unlock.into());
+            write_varint(&mut out, amount);
+        }
+        write_varint(&mut out, self.rate_schedule.len() as u128);
+        for &(at, half_life) in &self.rate_schedule {
+            write_varint(&mut out, at.into());
+            write_varint(&mut out, half_life.as_secs().into());
+        }
+        write_varint(&mut out, self.paused_at.map_or(0, |t| u128::from(t) + 1));
+        out
+    }
+
+    /// Decode a snapshot of any supported version. The result still needs [`Snapshot::migrate`].
+    pub fn from_bytes(mut bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let version = read_field(&mut bytes)?;
+        if !(1..=SNAPSHOT_VERSION).contains(&version) {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let mut snapshot = Self {
+            version,
+            decay_rate: DecayRate::from_bits(read_varint(&mut bytes)?),
+            total_deposited: read_varint(&mut bytes)?,
+            total_claimed: read_varint(&mut bytes)?,
+            last_update_principal: read_varint(&mut bytes)?,
+            last_update_timestamp: read_field(&mut bytes)?,
+            origin_principal: 0,
+            origin_timestamp: 0,
+            rounding: RoundingMode::Floor,
+            curve: Curve::default(),
+            cliff: 0,
+            cliff_end: None,
+            tranche_lock: 0,
+            tranches: Vec::new(),
+            rate_schedule: Vec::new(),
+            paused_at: None,
+        };
+        if version >= 2 {
+            snapshot.origin_principal = read_varint(&mut bytes)?;
+            snapshot.origin_timestamp = read_field(&mut bytes)?;
+        }
+        if version >= 3 {
+            snapshot.rounding = match read_varint(&mut bytes)? {
+                0 => RoundingMode::Floor,
+                1 => RoundingMode::Ceil,
+                2 => RoundingMode::HalfEven,
+                _ => return Err(SnapshotError::InvalidValue),
+            };
+        }
+        if version >= 4 {
+            snapshot.curve = match read_varint(&mut bytes)? {
+                0 => ExponentialCurve(snapshot.decay_rate).into(),
+                1 => {
+                    let (cliff, duration) = (read_field(&mut bytes)?, read_field(&mut bytes)?);
+                    if cliff > duration {
+                        return Err(SnapshotError::InvalidValue);
+                    }
+                    LinearCurve::new(duration).with_cliff(cliff).into()
+                }
+                2 => StepCurve::new(read_field(&mut bytes)?, read_field(&mut bytes)?).into(),
+                _ => return Err(SnapshotError::InvalidValue),
+            };
+        }
+        if version >= 5 {
+            snapshot.cliff = read_field(&mut bytes)?;
+            snapshot.cliff_end = if version >= 9 {
+                read_optional_field(&mut bytes)?
+            } else {
+                Some(read_field(&mut bytes)?)
+            };
+        }
+        if version >= 6 {
+            snapshot.tranche_lock = read_field(&mut bytes)?;
+            let len: usize = read_field(&mut bytes)?;
+            for _ in 0..len {
+                let unlock = read_field(&mut bytes)?;
+                if snapshot
+                    .tranches
+                    .last()
+                    .is_some_and(|&(last, _)| last >= unlock)
+                {
+                    return Err(SnapshotError::InvalidValue);
+                }
+                snapshot.tranches.push((unlock, read_varint(&mut bytes)?));
+            }
+        }
+        if version >= 7 {
+            let len: usize = read_field(&mut bytes)?;
+            for _ in 0..len {
+                let at = read_field(&mut bytes)?;
+                if snapshot
+                    .rate_schedule
+                    .last()
+                    .is_some_and(|&(last, _)| last >= at)
+                {
+                    return Err(SnapshotError::InvalidValue);
+                }
+                let half_life = HalfLife::seconds(read_field(&mut bytes)?);
+                snapshot.rate_schedule.push((at, half_life));
+            }
+        }
+        if version >= 8 {
+            snapshot.paused_at = read_optional_field(&mut bytes)?;
+        }
+        if !bytes.is_empty() {
+            return Err(SnapshotError::TrailingBytes);
+        }
+        Ok(snapshot)
+    }
+}
+
+fn rounding_code(rounding: RoundingMode) -> u128 {
+    match rounding {
+        RoundingMode::Floor => 0,
+        RoundingMode::Ceil => 1,
+        RoundingMode::HalfEven => 2,
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u128) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u128, SnapshotError> {
+    let mut v = 0u128;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = bytes.split_first().ok_or(SnapshotError::Truncated)?;
+        *bytes = rest;
+        let bits = u128::from(byte & 0x7F);
+        if shift >= 128 || (bits << shift) >> shift != bits {
+            return Err(SnapshotError::Overflow);
+        }
+        v |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(v);
+        }
+        shift += 7;
+    }
+}
+
+fn read_field<T: TryFrom<u128>>(bytes: &mut &[u8]) -> Result<T, SnapshotError> {
+    T::try_from(read_varint(bytes)?).map_err(|_| SnapshotError::Overflow)
+}
+
+fn read_optional_field(bytes: &mut &[u8]) -> Result<Option<u64>, SnapshotError> {
+    match read_varint(bytes)? {
+        0 => Ok(None),
+        t => u64::try_from(t - 1)
+            .map(Some)
+            .map_err(|_| SnapshotError::Overflow),
+    }
+}
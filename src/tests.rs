@@ -1,3 +1,4 @@
+use std::time::Duration;
 use token_stream::*;
 
 fn principal_after(p: u128, secs: u64, lambda: f64) -> u128 {
@@ -27,7 +28,7 @@ fn continuous_basic() {
         claimable, still_vesting
     );
 
-    assert_eq!(still_vesting, principal_after(100, 10, rate) as u128);
+    assert_eq!(still_vesting, principal_after(100, 10, rate));
 }
 
 #[test]
@@ -107,3 +108,1111 @@ fn dust_eventually_rounds_to_zero() {
     assert_eq!(claimed, 100);
     assert_eq!(b.balance_claimable(), 0);
 }
+
+#[test]
+fn large_principal_keeps_precision() {
+    clock_reset(0);
+    let mut b = TokenStream::new(0.01);
+
+    // Well above 2^53, where an f64 principal would already have lost the low digits.
+    let deposit = 1_000_000_000_000_000_000_000_000_000_007u128;
+    b.deposit(deposit);
+    assert_eq!(b.balance_still_vesting(), deposit);
+
+    wait(10);
+    // floor(P·e^{-10λ}) for the quantized λ, computed with 90 significant digits.
+    let exact = 904_837_418_035_959_571_280_677_806_241u128;
+    let tolerance = exact >> 56;
+    assert!(b.balance_still_vesting().abs_diff(exact) <= tolerance);
+    assert_eq!(b.total_vested() + b.balance_still_vesting(), deposit);
+}
+
+#[test]
+fn decay_is_exact_at_whole_half_lives() {
+    let ln2 = DecayRate::from_bits(0xB172_17F7_D1CF_79AB);
+    let p = 1u128 << 100;
+    assert_eq!(ln2.decay(p, 1), p >> 1);
+    assert_eq!(ln2.decay(p, 3), p >> 3);
+    assert_eq!(ln2.decay(p, 127), 0);
+    assert_eq!(ln2.decay(u128::MAX, u64::MAX), 0);
+}
+
+#[test]
+fn injected_clocks() {
+    let mut manual = TestClock::new();
+    manual.wait(10);
+    assert_eq!(Clock::now(&manual), 10);
+
+    // A stream owning a test clock is advanced through it.
+    let mut b = TokenStream::new(0.01).with_clock(manual);
+    b.deposit(100);
+    b.clock_mut().wait(10);
+    assert_eq!(b.balance_claimable(), vested_after(100, 10, 0.01));
+    assert_eq!(b.claim(), vested_after(100, 10, 0.01));
+
+    // A caller-supplied timestamp, e.g. the host's block time.
+    let block_time = std::cell::Cell::new(1_000);
+    let mut b = TokenStream::new(0.01).with_clock(|| block_time.get());
+    b.deposit(100);
+    block_time.set(1_010);
+    assert_eq!(b.balance_claimable(), vested_after(100, 10, 0.01));
+
+    // The thread-local test clock does not affect streams using another clock.
+    clock_reset(1_000_000);
+    assert_eq!(b.balance_claimable(), vested_after(100, 10, 0.01));
+
+    let system = SystemClock;
+    assert!(system.now() > 1_600_000_000);
+}
+
+#[test]
+fn explicit_timestamps() {
+    let rate = 0.01;
+    let mut b = TokenStream::new(rate);
+
+    b.deposit_at(100, 100).unwrap();
+    assert_eq!(b.balance_still_vesting_at(100), Ok(100));
+    assert_eq!(b.balance_claimable_at(110), Ok(vested_after(100, 10, rate)));
+    // Reading ahead does not move the stream.
+    assert_eq!(b.total_vested_at(1_000), Ok(vested_after(100, 900, rate)));
+    assert_eq!(b.claim_at(110), Ok(vested_after(100, 10, rate)));
+    assert_eq!(b.settle_at(110), Ok(principal_after(100, 10, rate)));
+
+    let regression = TimeRegression {
+        at: 105,
+        last_update: 110,
+    };
+    assert_eq!(b.balance_still_vesting_at(105), Err(regression));
+    assert_eq!(b.balance_claimable_at(105), Err(regression));
+    assert_eq!(b.total_vested_at(105), Err(regression));
+    assert_eq!(b.deposit_at(105, 1), Err(regression));
+    assert_eq!(b.claim_at(105), Err(regression));
+    assert_eq!(b.settle_at(105), Err(regression));
+    assert_eq!(b.unclaimed_total() + b.total_claimed(), 100);
+}
+
+#[test]
+fn half_life_units() {
+    clock_reset(0);
+    let mut b = TokenStream::new_from_half_life(HalfLife::days(1));
+    assert_eq!(b.half_life(), HalfLife::hours(24));
+    assert_eq!(b.half_life().as_secs(), 86_400);
+
+    b.deposit(1_000);
+    wait(3_600);
+    assert!(b.balance_still_vesting() > 900); // an hour is not a half-life
+    wait(86_400 - 3_600);
+    assert_eq!(b.balance_still_vesting(), 500);
+
+    b.set_half_life(Duration::from_secs(600).try_into().unwrap());
+    assert_eq!(b.half_life(), HalfLife::minutes(10));
+    wait(600);
+    assert_eq!(b.balance_still_vesting(), 250);
+
+    let short = Duration::from_millis(500);
+    assert_eq!(HalfLife::try_from(short), Err(ShortHalfLife(short)));
+    assert_eq!(
+        HalfLife::try_from(Duration::from_millis(1_500)),
+        Ok(HalfLife::seconds(1))
+    );
+
+    for secs in [1, 59, 86_400, 365 * 86_400, 3_000_000_000] {
+        let half_life = HalfLife::seconds(secs);
+        assert_eq!(DecayRate::from_half_life(half_life).half_life(), half_life);
+    }
+}
+
+#[test]
+fn snapshot_round_trip() {
+    clock_reset(0);
+    let mut b = TokenStream::new_from_half_life(HalfLife::hours(1));
+    b.deposit(u128::MAX / 3);
+    wait(1_000);
+    b.claim();
+    wait(500);
+
+    let bytes = b.to_bytes();
+    let restored = TokenStream::from_bytes(&bytes).unwrap();
+    assert_eq!(restored.snapshot(), b.snapshot());
+    assert_eq!(restored.balance_claimable(), b.balance_claimable());
+
+    // Small streams encode compactly.
+    let empty = TokenStream::new(0.0).to_bytes();
+    assert!(empty.len() < 24);
+
+    assert_eq!(
+        TokenStream::from_bytes(&bytes[..bytes.len() - 1]).err(),
+        Some(SnapshotError::Truncated)
+    );
+    let mut future = b.snapshot();
+    future.version = SNAPSHOT_VERSION + 1;
+    assert_eq!(
+        TokenStream::from_snapshot(future).err(),
+        Some(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1))
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    clock_reset(0);
+    let mut b = TokenStream::new_from_half_life(HalfLife::days(30));
+    b.deposit(1_000_000);
+    wait(86_400);
+    b.claim();
+
+    let json = serde_json::to_string(&b).unwrap();
+    assert!(json.contains(&format!("\"version\":{SNAPSHOT_VERSION}")));
+    let restored: TokenStream = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.snapshot(), b.snapshot());
+
+    // Penalties above 100% are rejected rather than panicking later.
+    let policy = PenaltyPolicy::new(2_500, ForfeitDestination::Burn);
+    let json = serde_json::to_string(&policy).unwrap();
+    assert_eq!(
+        serde_json::from_str::<PenaltyPolicy>(&json).unwrap(),
+        policy
+    );
+    let invalid = json.replace("2500", "20000");
+    assert!(serde_json::from_str::<PenaltyPolicy>(&invalid).is_err());
+}
+
+#[test]
+fn partial_claims() {
+    clock_reset(0);
+    let rate = 0.01;
+    let mut b = TokenStream::new(rate);
+
+    b.deposit(100);
+    wait(10);
+    assert_eq!(b.balance_claimable(), 10);
+
+    assert_eq!(b.claim_amount(4), Ok(4));
+    assert_eq!(b.balance_claimable(), 6);
+    assert_eq!(
+        b.claim_amount(7),
+        Err(ClaimError::InsufficientClaimable {
+            requested: 7,
+            claimable: 6
+        })
+    );
+    assert_eq!(b.total_claimed(), 4);
+
+    // Partial claims leave the vesting curve untouched.
+    wait(10);
+    assert_eq!(b.balance_still_vesting(), principal_after(100, 20, rate));
+    assert_eq!(b.claim(), vested_after(100, 20, rate) - 4);
+}
+
+#[test]
+fn checked_operations_surface_errors() {
+    clock_reset(0);
+    let mut b = TokenStream::new(0.01);
+
+    b.checked_deposit(u128::MAX - 1).unwrap();
+    assert_eq!(b.checked_deposit(2), Err(TokenStreamError::Overflow));
+    assert_eq!(b.unclaimed_total(), u128::MAX - 1);
+
+    wait(10);
+    let claimed = b.checked_claim().unwrap();
+    assert_eq!(b.checked_total_vested(), Ok(claimed));
+    assert_eq!(b.checked_unclaimed_total(), Ok(u128::MAX - 1 - claimed));
+    assert_eq!(
+        b.checked_claim_at(5),
+        Err(TokenStreamError::TimeRegression(TimeRegression {
+            at: 5,
+            last_update: 10
+        }))
+    );
+
+    // A corrupted snapshot claims more than ever vested.
+    let mut corrupt = TokenStream::new(0.01).snapshot();
+    corrupt.total_deposited = 100;
+    corrupt.total_claimed = 50;
+    corrupt.last_update_principal = 100;
+    corrupt.last_update_timestamp = 10;
+    corrupt.origin_principal = 100;
+    corrupt.origin_timestamp = 10;
+    let mut c = TokenStream::from_snapshot(corrupt).unwrap();
+    assert_eq!(c.balance_claimable(), 0); // the unchecked API silently clamps
+    assert_eq!(
+        c.checked_claim(),
+        Err(TokenStreamError::InvariantViolation(
+            InvariantViolation::ClaimedExceedsVested {
+                claimed: 50,
+                vested: 0
+            }
+        ))
+    );
+}
+
+#[test]
+fn invariant_report() {
+    clock_reset(0);
+    let mut b = TokenStream::new(0.01);
+    b.deposit(1_000);
+    wait(30);
+    b.claim_amount(100).unwrap();
+    wait(30);
+    assert_eq!(b.check_invariants(), Ok(()));
+    assert_eq!(
+        b.total_claimed() + b.balance_still_vesting() + b.balance_claimable(),
+        1_000
+    );
+
+    let mut corrupt = b.snapshot();
+    corrupt.total_claimed = 900;
+    let c = TokenStream::from_snapshot(corrupt).unwrap();
+    let report = c.check_invariants().unwrap_err();
+    assert_eq!(report.violations.len(), 2);
+    assert!(matches!(
+        report.violations[0],
+        InvariantViolation::ClaimedExceedsVested { claimed: 900, .. }
+    ));
+    assert!(matches!(
+        report.violations[1],
+        InvariantViolation::PrincipalExceedsUnclaimed { unclaimed: 100, .. }
+    ));
+    println!("{report}");
+
+    // Times before the last update are rejected rather than reported as violations.
+    b.settle_at(100).unwrap();
+    assert_eq!(
+        b.check_invariants_at(10),
+        Err(InvariantCheckError::TimeRegression(TimeRegression {
+            at: 10,
+            last_update: 100
+        }))
+    );
+}
+
+/// Small deterministic PRNG, so random schedules are reproducible.
+struct XorShift(u64);
+
+impl XorShift {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+#[test]
+fn settle_frequency_does_not_change_vesting() {
+    let rate = 0.01;
+    let mut once = TokenStream::new(rate);
+    let mut every_second = TokenStream::new(rate);
+    once.deposit_at(0, 1_000).unwrap();
+    every_second.deposit_at(0, 1_000).unwrap();
+
+    for t in 1..=300 {
+        every_second.settle_at(t).unwrap();
+    }
+    assert_eq!(once.total_vested_at(300), every_second.total_vested_at(300));
+    assert_eq!(
+        every_second.balance_still_vesting_at(300),
+        Ok(principal_after(1_000, 300, rate))
+    );
+}
+
+#[test]
+fn path_independence_over_random_schedules() {
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..200 {
+        let half_life = HalfLife::seconds(1 + rng.below(5_000));
+        let mut quiet = TokenStream::new_from_half_life(half_life);
+        let mut busy = TokenStream::new_from_half_life(half_life);
+
+        let mut t = 0;
+        for _ in 0..50 {
+            t += rng.below(1_000);
+            match rng.below(5) {
+                0 => {
+                    let amount = u128::from(rng.below(1 << 40));
+                    quiet.deposit_at(t, amount).unwrap();
+                    busy.deposit_at(t, amount).unwrap();
+                }
+                1 => {
+                    busy.settle_at(t).unwrap();
+                }
+                2 => {
+                    busy.claim_at(t).unwrap();
+                }
+                3 => {
+                    let half = busy.balance_claimable_at(t).unwrap() / 2;
+                    busy.claim_amount_at(t, half).unwrap();
+                }
+                _ => busy.deposit_at(t, 0).unwrap(),
+            }
+            assert_eq!(quiet.total_vested_at(t), busy.total_vested_at(t));
+            assert_eq!(
+                busy.total_claimed() + busy.balance_claimable_at(t).unwrap(),
+                busy.total_vested_at(t).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn version_1_snapshots_migrate() {
+    // Version 1: rate, deposited, claimed, principal and timestamp as varints.
+    let v1 = [1, 0, 100, 0, 100, 5];
+    let b = TokenStream::from_bytes(&v1).unwrap();
+    let snapshot = b.snapshot();
+    assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+    assert_eq!(snapshot.origin_principal, 100);
+    assert_eq!(snapshot.origin_timestamp, 5);
+    assert_eq!(b.balance_still_vesting_at(1_000), Ok(100));
+}
+
+#[test]
+fn rounding_modes() {
+    let rate = 0.01;
+    // 100·e^{-0.12} = 88.69...
+    for (mode, expected) in [
+        (RoundingMode::Floor, 88),
+        (RoundingMode::Ceil, 89),
+        (RoundingMode::HalfEven, 89),
+    ] {
+        let mut b = TokenStream::new(rate).with_rounding(mode);
+        b.deposit_at(0, 100).unwrap();
+        assert_eq!(b.balance_still_vesting_at(12), Ok(expected));
+        assert_eq!(b.claim_at(12), Ok(100 - expected));
+
+        // Deposits keep what was claimable, whatever the rounding.
+        let before = b.balance_claimable_at(40).unwrap();
+        b.deposit_at(40, 1_000).unwrap();
+        assert_eq!(b.balance_claimable_at(40), Ok(before));
+        assert_eq!(b.check_invariants_at(1_000), Ok(()));
+        assert_eq!(b.rounding(), mode);
+        assert_eq!(
+            TokenStream::from_bytes(&b.to_bytes()).unwrap().rounding(),
+            mode
+        );
+    }
+
+    // Ceil only reaches zero once the exact amount is below 2^-64.
+    let mut b = TokenStream::new(0.01).with_rounding(RoundingMode::Ceil);
+    b.deposit_at(0, 100).unwrap();
+    assert_eq!(b.balance_still_vesting_at(1_000), Ok(1));
+    assert_eq!(b.balance_still_vesting_at(1_000_000), Ok(0));
+}
+
+#[test]
+fn registry_tracks_accounts() {
+    clock_reset(0);
+    let rate = 0.01;
+    let mut registry = StreamRegistry::new(rate);
+
+    registry.deposit("alice", 100);
+    registry.deposit("bob", 200);
+    wait(10);
+    registry.deposit("alice", 100);
+
+    assert_eq!(registry.len(), 2);
+    assert_eq!(registry.balance_claimable(&"alice"), 10);
+    assert_eq!(registry.claim(&"bob"), vested_after(200, 10, rate));
+    assert_eq!(registry.claim(&"carol"), 0);
+
+    wait(10);
+    assert_eq!(registry.total_deposited(), 400);
+    assert_eq!(
+        registry.total_claimed() + registry.total_claimable() + registry.total_still_vesting(),
+        400
+    );
+    assert_eq!(
+        registry.total_vested(),
+        registry.iter().map(|(_, s)| s.total_vested()).sum::<u128>()
+    );
+    let accounts: Vec<_> = registry.iter().map(|(k, _)| *k).collect();
+    assert_eq!(accounts, ["alice", "bob"]);
+
+    registry.set_half_life(HalfLife::seconds(10));
+    assert_eq!(
+        registry.get(&"bob").unwrap().half_life(),
+        HalfLife::seconds(10)
+    );
+    registry.deposit("carol", 64);
+    wait(20);
+    assert_eq!(registry.balance_still_vesting(&"carol"), 16);
+}
+
+#[test]
+fn pool_matches_individual_streams() {
+    clock_reset(0);
+    let rate = 0.01;
+    let mut pool = RewardPool::new(rate);
+    assert_eq!(pool.distribute(100), Err(NoShareholders));
+
+    pool.set_shares("alice", 1);
+    pool.set_shares("bob", 3);
+    pool.distribute(4_000).unwrap();
+
+    // The same rewards, deposited into one stream per holder.
+    let mut alice = TokenStream::new(rate);
+    let mut bob = TokenStream::new(rate);
+    let mut carol = TokenStream::new(rate);
+    alice.deposit(1_000);
+    bob.deposit(3_000);
+
+    wait(10);
+    pool.set_shares("carol", 4);
+    assert_eq!(pool.claim(&"alice"), alice.claim());
+
+    wait(10);
+    pool.distribute(8_000).unwrap();
+    alice.deposit(1_000);
+    bob.deposit(3_000);
+    carol.deposit(4_000);
+
+    wait(25);
+    for (account, stream) in [("alice", &alice), ("bob", &bob), ("carol", &carol)] {
+        let claimable = pool.balance_claimable(&account);
+        assert!(claimable.abs_diff(stream.balance_claimable()) <= 1);
+        let still_vesting = pool.balance_still_vesting(&account);
+        assert!(still_vesting.abs_diff(stream.balance_still_vesting()) <= 1);
+    }
+    let claimed = pool.claim(&"bob");
+    assert!(claimed.abs_diff(bob.claim()) <= 1);
+    assert_eq!(pool.balance_claimable(&"bob"), 0);
+
+    // Leaving the pool keeps what was already distributed vesting.
+    pool.set_shares("carol", 0);
+    pool.distribute(1_000).unwrap();
+    wait(1_000_000);
+    assert_eq!(pool.balance_still_vesting(&"carol"), 0);
+    assert!(pool.balance_claimable(&"carol").abs_diff(4_000) <= 1);
+    assert_eq!(pool.total_distributed(), 13_000);
+    assert_eq!(pool.total_shares(), 4);
+}
+
+#[test]
+fn event_log_replays_exactly() {
+    clock_reset(0);
+    let mut b = TokenStream::new_from_half_life(HalfLife::seconds(100)).with_event_log();
+    b.deposit(1_000);
+    wait(30);
+    b.claim();
+    wait(30);
+    b.settle();
+    b.set_half_life(HalfLife::seconds(50));
+    b.deposit(500);
+    wait(40);
+    b.claim_amount(10).unwrap();
+
+    let log = b.event_log().unwrap();
+    let kinds: Vec<_> = log.events().iter().map(|e| &e.kind).collect();
+    assert_eq!(kinds.len(), 7);
+    assert_eq!(kinds[0], &EventKind::Open);
+    assert_eq!(kinds[6], &EventKind::Claim { amount: 10 });
+    assert_eq!(log.events()[6].timestamp, 100);
+
+    let replayed = TokenStream::replay(log.events()).unwrap();
+    assert_eq!(replayed.snapshot(), b.snapshot());
+    assert_eq!(replayed.event_log(), b.event_log());
+
+    let mut tampered = log.events().to_vec();
+    tampered[1].kind = EventKind::Deposit { amount: 1_001 };
+    assert_eq!(
+        TokenStream::replay(&tampered).err(),
+        Some(ReplayError::Mismatch { index: 1 })
+    );
+    assert_eq!(
+        TokenStream::replay(&log.events()[1..]).err(),
+        Some(ReplayError::MissingOpen)
+    );
+}
+
+#[test]
+fn historical_queries() {
+    clock_reset(100);
+    let rate = 0.01;
+    let mut b = TokenStream::new(rate).with_event_log();
+    b.deposit(1_000);
+    wait(50);
+    b.claim();
+    wait(50);
+    b.deposit(1_000);
+    wait(50);
+    b.claim_amount(5).unwrap();
+
+    let history = History::from(b.event_log().unwrap());
+    assert_eq!(history.checkpoints().len(), 5);
+
+    // Before the first deposit only the empty opening state exists.
+    assert_eq!(history.vested_at(50), Some(0));
+    assert_eq!(
+        history.still_vesting_at(120),
+        Some(1_000 - vested_after(1_000, 20, rate))
+    );
+    assert_eq!(history.vested_at(120), Some(vested_after(1_000, 20, rate)));
+    assert_eq!(history.claimed_at(149), Some(0));
+    assert_eq!(history.claimed_at(150), Some(vested_after(1_000, 50, rate)));
+
+    // Just before the second deposit, and after it.
+    let before = principal_after(1_000, 99, rate);
+    assert_eq!(history.still_vesting_at(199), Some(before));
+    let after = principal_after(1_000, 100, rate) + 1_000;
+    assert_eq!(history.still_vesting_at(200), Some(after));
+    assert_eq!(history.claimed_at(10_000), Some(b.total_claimed()));
+    assert_eq!(
+        history.vested_at(10_000),
+        Some(b.total_vested_at(10_000).unwrap())
+    );
+
+    assert_eq!(History::default().vested_at(99), None);
+}
+
+#[test]
+fn inverse_queries() {
+    clock_reset(0);
+    let rate = 0.01;
+    let mut b = TokenStream::new(rate);
+    b.deposit(1_000);
+    wait(10);
+    b.claim();
+
+    let target = 500;
+    let secs = b.time_until_claimable(target).unwrap();
+    assert!(b.balance_claimable_at(secs + 10).unwrap() >= target);
+    assert!(b.balance_claimable_at(secs + 9).unwrap() < target);
+    assert_eq!(b.time_until_claimable(0), Some(0));
+    assert_eq!(b.time_until_claimable(b.unclaimed_total() + 1), None);
+
+    // The last token only vests once floor(1000·e^{-λt}) reaches zero.
+    let all = b.time_until_claimable(b.unclaimed_total()).unwrap();
+    assert_eq!(b.balance_still_vesting_at(all + 10), Ok(0));
+    assert_eq!(b.balance_still_vesting_at(all + 9), Ok(1));
+
+    let secs = b.time_until_still_vesting_below(100).unwrap();
+    assert_eq!(b.balance_still_vesting_at(secs + 10), Ok(99));
+    assert_eq!(b.time_until_still_vesting_below(0), None);
+
+    let frozen = TokenStream::new(0.0);
+    assert_eq!(frozen.time_until_still_vesting_below(1), Some(0));
+}
+
+#[test]
+fn projection_matches_stream() {
+    clock_reset(0);
+    let mut b = TokenStream::new_from_half_life(HalfLife::hours(1));
+    b.deposit(1_000_000);
+    wait(100);
+
+    let plan = vec![
+        (1_800, EventKind::Claim { amount: u128::MAX }),
+        (900, EventKind::Deposit { amount: 50_000 }),
+        (3_000, EventKind::Claim { amount: 1_000 }),
+    ];
+    let samples: Vec<_> = b.project_with(0, 3_600, 600, plan.clone()).collect();
+    assert_eq!(samples.len(), 7);
+
+    let mut live = TokenStream::from_snapshot(b.snapshot()).unwrap();
+    let mut plan = plan;
+    plan.sort_by_key(|&(t, _)| t);
+    let mut plan = plan.into_iter().peekable();
+    for (t, vested, still_vesting) in samples {
+        while let Some((at, kind)) = plan.next_if(|&(at, _)| at <= t) {
+            clock_reset(at);
+            match kind {
+                EventKind::Deposit { amount } => live.deposit(amount),
+                EventKind::Claim { amount } => {
+                    live.claim_amount(amount.min(live.balance_claimable()))
+                        .unwrap();
+                }
+                _ => unreachable!(),
+            }
+        }
+        clock_reset(t);
+        assert_eq!(vested, live.total_vested());
+        assert_eq!(still_vesting, live.balance_still_vesting());
+    }
+
+    let idle: Vec<_> = b.project(0, 7_200, 3_600).collect();
+    assert_eq!(idle[0], (0, 0, 1_000_000));
+    assert_eq!(idle[1].2, 500_000);
+    assert_eq!(idle[2].2, 250_000);
+    assert_eq!(b.project(0, 7_200, 0).count(), 0);
+}
+
+#[test]
+fn vesting_curves() {
+    assert!(Curve::default().is_deposit_neutral());
+    assert!(!Curve::from(LinearCurve::new(100)).is_deposit_neutral());
+    assert!(!Curve::from(StepCurve::new(10, 4)).is_deposit_neutral());
+
+    // Linear over 1000s, nothing before 250s.
+    let mut b = TokenStream::from_curve(LinearCurve::new(1_000).with_cliff(250));
+    b.deposit_at(0, 1_000).unwrap();
+    assert_eq!(b.balance_claimable_at(249), Ok(0));
+    assert_eq!(b.balance_claimable_at(250), Ok(250));
+    assert_eq!(b.balance_claimable_at(600), Ok(600));
+    assert_eq!(b.balance_claimable_at(5_000), Ok(1_000));
+    assert_eq!(b.decay_rate(), DecayRate::default());
+
+    // Depositing restarts the schedule for whatever was still vesting.
+    assert_eq!(b.claim_at(600), Ok(600));
+    b.deposit_at(600, 600).unwrap();
+    assert_eq!(b.balance_claimable_at(849), Ok(0));
+    assert_eq!(b.balance_claimable_at(1_100), Ok(500));
+    assert_eq!(b.check_invariants_at(1_100), Ok(()));
+
+    // A quarter vests at the end of each 10s epoch.
+    let mut b = TokenStream::from_curve(StepCurve::new(10, 4));
+    b.deposit_at(0, 1_000).unwrap();
+    assert_eq!(b.balance_still_vesting_at(9), Ok(1_000));
+    assert_eq!(b.balance_still_vesting_at(10), Ok(750));
+    assert_eq!(b.balance_still_vesting_at(39), Ok(250));
+    assert_eq!(b.balance_still_vesting_at(40), Ok(0));
+    assert_eq!(b.time_until_claimable(600), Some(30));
+
+    let restored = TokenStream::from_bytes(&b.to_bytes()).unwrap();
+    assert_eq!(restored.curve(), b.curve());
+    assert_eq!(restored.snapshot(), b.snapshot());
+
+    // Rate changes switch to exponential decay.
+    b.set_half_life_at(20, HalfLife::seconds(10)).unwrap();
+    assert_eq!(
+        b.curve(),
+        Curve::Exponential(ExponentialCurve(DecayRate::from_half_life(
+            HalfLife::seconds(10)
+        )))
+    );
+    assert_eq!(b.balance_still_vesting_at(30), Ok(250));
+}
+
+#[test]
+fn cliff() {
+    clock_reset(0);
+    let half_life = HalfLife::seconds(10);
+    let mut b = TokenStream::new_from_half_life(half_life).with_cliff(100);
+    assert_eq!(b.cliff_end(), None);
+    b.deposit_at(20, 1_000).unwrap();
+    assert_eq!(b.cliff_end(), Some(120));
+
+    // Deposits during the cliff wait for it, then vest together.
+    b.deposit_at(70, 1_000).unwrap();
+    assert_eq!(b.balance_claimable_at(119), Ok(0));
+    assert_eq!(b.claim_at(120), Ok(0));
+    assert_eq!(b.balance_still_vesting_at(130), Ok(1_000));
+    assert_eq!(b.time_until_claimable(1_000), Some(10));
+
+    // Later deposits vest right away; the cliff does not restart.
+    b.deposit_at(130, 1_000).unwrap();
+    assert_eq!(b.cliff_end(), Some(120));
+    assert_eq!(b.balance_still_vesting_at(140), Ok(1_000));
+    assert_eq!(b.check_invariants_at(140), Ok(()));
+
+    let restored = TokenStream::from_bytes(&b.to_bytes()).unwrap();
+    assert_eq!(restored.snapshot(), b.snapshot());
+    assert_eq!(
+        restored.balance_claimable_at(140),
+        b.balance_claimable_at(140)
+    );
+}
+
+#[test]
+fn tranche_locks() {
+    let half_life = HalfLife::seconds(10);
+    let mut b = TokenStream::new_from_half_life(half_life).with_tranche_lock(100);
+    let mut untouched = TokenStream::new_from_half_life(half_life).with_tranche_lock(100);
+    for s in [&mut b, &mut untouched] {
+        s.deposit_at(0, 1_000).unwrap();
+        s.deposit_at(50, 1_000).unwrap();
+    }
+
+    // Each deposit waits for its own lock.
+    assert_eq!(b.balance_claimable_at(99), Ok(0));
+    assert_eq!(b.balance_locked_at(99), Ok(2_000));
+    assert_eq!(b.balance_still_vesting_at(110), Ok(1_500));
+    assert_eq!(b.balance_locked_at(110), Ok(1_000));
+    assert_eq!(b.claim_at(110), Ok(500));
+
+    let restored = TokenStream::from_bytes(&b.to_bytes()).unwrap();
+    assert_eq!(restored.snapshot(), b.snapshot());
+
+    // The second tranche joins the curve at 150: floor(1000/32) + 1000, halved by 160.
+    assert_eq!(b.balance_still_vesting_at(160), Ok(515));
+    assert_eq!(b.balance_locked_at(160), Ok(0));
+    b.settle_at(155).unwrap();
+    b.deposit_at(158, 0).unwrap();
+    for t in [160, 175, 400] {
+        assert_eq!(
+            b.balance_still_vesting_at(t),
+            untouched.balance_still_vesting_at(t)
+        );
+        assert_eq!(restored.total_vested_at(t), b.total_vested_at(t));
+        assert_eq!(b.check_invariants_at(t), Ok(()));
+    }
+}
+
+#[test]
+fn multi_rate_stream() {
+    clock_reset(0);
+    let fast = DecayRate::from_half_life(HalfLife::seconds(10));
+    let slow = DecayRate::from_half_life(HalfLife::seconds(100));
+    let mut m = MultiRateStream::new(2);
+    m.deposit(fast, 1_000).unwrap();
+    m.deposit(slow, 1_000).unwrap();
+    wait(100);
+    m.deposit(slow, 1_000).unwrap();
+    assert_eq!(m.len(), 2);
+
+    // Each class vests at its own rate: the fast one is done, the slow one is at 500 + 1000.
+    assert!(m.balance_still_vesting().abs_diff(1_500) <= 2);
+    assert_eq!(
+        m.balance_claimable() + m.balance_still_vesting(),
+        m.total_deposited()
+    );
+
+    let other = DecayRate::from_half_life(HalfLife::seconds(1));
+    assert_eq!(m.deposit(other, 1), Err(TooManyRates));
+
+    // Once a class is fully claimed it makes room for a new one.
+    wait(1_000);
+    assert_eq!(m.claim(), 2_999);
+    assert_eq!(m.get(fast).unwrap().unclaimed_total(), 0);
+    m.deposit(other, 1).unwrap();
+    assert!(m.get(fast).is_none());
+    assert_eq!(m.total_deposited(), 3_001);
+    assert_eq!(m.total_claimed(), 2_999);
+}
+
+#[test]
+fn scheduled_rate_changes() {
+    clock_reset(0);
+    let mut a = TokenStream::new_from_half_life(HalfLife::seconds(50)).with_event_log();
+    let mut b = TokenStream::new_from_half_life(HalfLife::seconds(50));
+    a.deposit_at(0, 1_000_000).unwrap();
+    b.deposit_at(0, 1_000_000).unwrap();
+    a.schedule_half_life(100, HalfLife::seconds(100)).unwrap();
+    assert_eq!(
+        a.schedule_half_life_at(10, 5, HalfLife::seconds(1)),
+        Err(TimeRegression {
+            at: 5,
+            last_update: 10
+        })
+    );
+    assert_eq!(
+        a.scheduled_half_lives().collect::<Vec<_>>(),
+        [(100, HalfLife::seconds(100))]
+    );
+    let restored = TokenStream::from_bytes(&a.to_bytes()).unwrap();
+
+    // Nobody calls set_half_life on `a` at 100, yet it matches `b` which does.
+    assert_eq!(a.balance_still_vesting_at(50), Ok(500_000));
+    b.set_half_life_at(100, HalfLife::seconds(100)).unwrap();
+    for t in [100, 150, 200, 400] {
+        assert_eq!(a.balance_still_vesting_at(t), b.balance_still_vesting_at(t));
+        assert_eq!(restored.total_vested_at(t), a.total_vested_at(t));
+    }
+    assert_eq!(a.balance_still_vesting_at(200), Ok(125_000));
+
+    clock_reset(300);
+    assert_eq!(a.half_life(), HalfLife::seconds(100));
+    assert_eq!(a.scheduled_half_lives().count(), 0);
+    a.claim();
+    let history = History::from(a.event_log().unwrap());
+    assert_eq!(
+        history.still_vesting_at(150),
+        b.balance_still_vesting_at(150).ok()
+    );
+    let replayed = TokenStream::replay(a.event_log().unwrap().events()).unwrap();
+    assert_eq!(replayed.snapshot(), a.snapshot());
+}
+
+#[test]
+fn pause_and_resume() {
+    let half_life = HalfLife::seconds(10);
+    let mut a = TokenStream::new_from_half_life(half_life).with_event_log();
+    let mut b = TokenStream::new_from_half_life(half_life);
+    a.deposit_at(0, 1_000).unwrap();
+    b.deposit_at(0, 1_000).unwrap();
+
+    // Nothing vests or can be claimed while paused.
+    a.pause_at(10).unwrap();
+    assert!(a.is_paused());
+    assert_eq!(a.balance_still_vesting_at(50), Ok(500));
+    assert_eq!(a.balance_claimable_at(50), Ok(500));
+    assert_eq!(a.checked_claim_at(50), Err(TokenStreamError::Paused));
+    assert_eq!(a.claim_amount_at(50, 1), Err(ClaimError::Paused));
+    assert_eq!(a.time_until_claimable(1), None);
+    a.deposit_at(30, 500).unwrap();
+    assert_eq!(a.balance_still_vesting_at(50), Ok(1_000));
+    b.deposit_at(10, 500).unwrap();
+
+    let restored = TokenStream::from_bytes(&a.to_bytes()).unwrap();
+    assert!(restored.is_paused());
+    assert_eq!(restored.balance_still_vesting_at(55), Ok(1_000));
+
+    // After resuming, `a` runs 50 seconds behind `b`.
+    a.resume_at(60).unwrap();
+    for t in [60, 70, 95, 200] {
+        assert_eq!(
+            a.balance_still_vesting_at(t),
+            b.balance_still_vesting_at(t - 50)
+        );
+    }
+    assert_eq!(a.claim_at(70), Ok(1_000));
+    assert_eq!(a.check_invariants_at(70), Ok(()));
+
+    let replayed = TokenStream::replay(a.event_log().unwrap().events()).unwrap();
+    assert_eq!(replayed.snapshot(), a.snapshot());
+}
+
+#[test]
+fn projection_through_pause() {
+    let mut b = TokenStream::new_from_half_life(HalfLife::seconds(10));
+    b.deposit_at(0, 1_000).unwrap();
+    let plan = vec![
+        (5, EventKind::Pause),
+        (20, EventKind::Claim { amount: 10 }),
+        (30, EventKind::Resume),
+        (30, EventKind::Claim { amount: 10 }),
+    ];
+    let samples: Vec<_> = b.project_with(0, 40, 10, plan).collect();
+    assert_eq!(samples.len(), 5);
+
+    // Frozen while paused, and the claim planned then is skipped.
+    assert_eq!((samples[1].1, samples[1].2), (samples[3].1, samples[3].2));
+
+    b.pause_at(5).unwrap();
+    b.resume_at(30).unwrap();
+    b.claim_amount_at(30, 10).unwrap();
+    assert_eq!(
+        samples[4],
+        (
+            40,
+            b.total_vested_at(40).unwrap(),
+            b.balance_still_vesting_at(40).unwrap()
+        )
+    );
+}
+
+#[test]
+fn revoke_and_clawback() {
+    let half_life = HalfLife::seconds(10);
+    let mut b = TokenStream::new_from_half_life(half_life).with_event_log();
+    b.deposit_at(0, 1_000).unwrap();
+    assert_eq!(b.claim_at(10), Ok(500));
+
+    // A partial clawback leaves the rest vesting on the same curve.
+    assert_eq!(b.clawback_at(20, 100), Ok(100));
+    assert_eq!(b.balance_still_vesting_at(20), Ok(150));
+    assert_eq!(b.balance_still_vesting_at(30), Ok(75));
+    assert_eq!(b.total_deposited(), 900);
+
+    // Revoking takes whatever is still vesting; vested but unclaimed amounts stay claimable.
+    assert_eq!(b.revoke_at(30), Ok(75));
+    assert_eq!(b.balance_still_vesting_at(100), Ok(0));
+    assert_eq!(b.balance_claimable_at(100), Ok(325));
+    assert_eq!(b.total_deposited(), 825);
+    assert_eq!(b.check_invariants_at(100), Ok(()));
+    assert_eq!(b.revoke_at(40), Ok(0));
+
+    // Locked tranches furthest from unlocking go first.
+    let mut t = TokenStream::new_from_half_life(half_life).with_tranche_lock(100);
+    t.deposit_at(0, 1_000).unwrap();
+    t.deposit_at(50, 1_000).unwrap();
+    assert_eq!(t.clawback_at(60, 1_500), Ok(1_500));
+    assert_eq!(t.balance_locked_at(60), Ok(500));
+    assert_eq!(t.balance_still_vesting_at(110), Ok(250));
+
+    // Other curves keep their schedule: what remains still vests by the original end.
+    let mut l = TokenStream::from_curve(LinearCurve::new(1_000));
+    l.deposit_at(0, 1_000).unwrap();
+    assert_eq!(l.clawback_at(500, 100), Ok(100));
+    assert_eq!(l.balance_still_vesting_at(500), Ok(400));
+    assert_eq!(l.balance_still_vesting_at(750), Ok(200));
+    assert_eq!(l.balance_still_vesting_at(1_000), Ok(0));
+    assert_eq!(l.revoke_at(750), Ok(200));
+    assert_eq!(l.total_vested_at(1_000), Ok(700));
+    let mut s = TokenStream::from_curve(StepCurve::new(100, 10));
+    s.deposit_at(0, 1_000).unwrap();
+    assert_eq!(s.clawback_at(250, 80), Ok(80));
+    assert_eq!(s.balance_still_vesting_at(250), Ok(720));
+    assert_eq!(s.balance_still_vesting_at(500), Ok(450));
+    assert_eq!(s.balance_still_vesting_at(1_000), Ok(0));
+
+    // Deposits after a full revoke do not restart the cliff.
+    let mut c = TokenStream::new_from_half_life(half_life).with_cliff(100);
+    c.deposit_at(0, 1_000).unwrap();
+    assert_eq!(c.revoke_at(50), Ok(1_000));
+    c.deposit_at(60, 1_000).unwrap();
+    assert_eq!(c.cliff_end(), Some(100));
+    assert_eq!(c.balance_still_vesting_at(110), Ok(500));
+    let restored = TokenStream::from_bytes(&c.to_bytes()).unwrap();
+    assert_eq!(restored.cliff_end(), Some(100));
+
+    let replayed = TokenStream::replay(b.event_log().unwrap().events()).unwrap();
+    assert_eq!(replayed.snapshot(), b.snapshot());
+}
+
+#[test]
+fn split_and_merge() {
+    let half_life = HalfLife::seconds(10);
+    let mut a = TokenStream::new_from_half_life(half_life).with_event_log();
+    a.deposit_at(0, 1_000).unwrap();
+    assert_eq!(a.claim_at(10), Ok(500));
+
+    let vested = a.total_vested_at(10).unwrap();
+    let mut part = a.split_at(10, 200).unwrap();
+    assert_eq!(a.total_vested_at(10), Ok(vested));
+    assert_eq!(part.total_vested_at(10), Ok(0));
+    assert_eq!(a.balance_still_vesting_at(20), Ok(150));
+    assert_eq!(part.balance_still_vesting_at(20), Ok(100));
+    assert_eq!(part.half_life(), a.half_life());
+    assert_eq!(
+        a.split_at(20, 151).err(),
+        Some(SplitError::InsufficientStillVesting {
+            requested: 151,
+            still_vesting: 150
+        })
+    );
+    assert_eq!(part.claim_at(20), Ok(100));
+
+    // Merging adds everything back up exactly.
+    let (vested_a, vested_part) = (a.total_vested_at(30), part.total_vested_at(30));
+    a.merge_at(30, part).unwrap();
+    assert_eq!(
+        a.total_vested_at(30),
+        Ok(vested_a.unwrap() + vested_part.unwrap())
+    );
+    assert_eq!(a.total_deposited(), 1_000);
+    assert_eq!(a.total_claimed(), 600);
+    assert_eq!(a.balance_still_vesting_at(40), Ok(62));
+    assert_eq!(a.check_invariants_at(40), Ok(()));
+
+    let replayed = TokenStream::replay(a.event_log().unwrap().events()).unwrap();
+    assert_eq!(replayed.snapshot(), a.snapshot());
+
+    // Linear curves keep their schedule across splits and merges.
+    let mut l = TokenStream::from_curve(LinearCurve::new(1_000));
+    l.deposit_at(0, 1_000).unwrap();
+    let part = l.split_at(500, 100).unwrap();
+    assert_eq!(part.balance_still_vesting_at(500), Ok(100));
+    assert_eq!(l.balance_still_vesting_at(500), Ok(400));
+    assert_eq!(part.balance_still_vesting_at(1_000), Ok(0));
+    assert_eq!(l.balance_still_vesting_at(1_000), Ok(0));
+    l.merge_at(750, part).unwrap();
+    assert_eq!(l.total_vested_at(750), Ok(750));
+    assert_eq!(l.total_vested_at(1_000), Ok(1_000));
+
+    let mut m = TokenStream::from_curve(LinearCurve::new(1_000));
+    let mut n = TokenStream::from_curve(LinearCurve::new(1_000));
+    m.deposit_at(0, 1_000).unwrap();
+    n.deposit_at(0, 1_000).unwrap();
+    m.merge_at(500, n).unwrap();
+    assert_eq!(m.total_vested_at(500), Ok(1_000));
+    assert_eq!(m.total_vested_at(1_000), Ok(2_000));
+    let mut late = TokenStream::from_curve(LinearCurve::new(1_000));
+    late.deposit_at(100, 1_000).unwrap();
+    assert_eq!(m.merge_at(500, late), Err(MergeError::VestsDifferently));
+
+    clock_reset(40);
+    let quarter = a.split_fraction(1, 4).unwrap();
+    assert_eq!(quarter.balance_still_vesting(), 15);
+    assert_eq!(a.balance_still_vesting(), 47);
+    for (numerator, denominator) in [(0, 0), (5, 4)] {
+        assert_eq!(
+            a.split_fraction_at(40, numerator, denominator).err(),
+            Some(SplitError::InvalidFraction {
+                numerator,
+                denominator
+            })
+        );
+    }
+    assert_eq!(a.split_fraction_at(40, 0, 4).unwrap().total_deposited(), 0);
+}
+
+#[test]
+fn merge_rejects_other_rates() {
+    let mut a = TokenStream::new_from_half_life(HalfLife::seconds(10));
+    let mut b = TokenStream::new_from_half_life(HalfLife::seconds(20));
+    a.deposit_at(0, 100).unwrap();
+    b.deposit_at(0, 100).unwrap();
+    let other = b.snapshot();
+    assert_eq!(a.merge_at(0, b), Err(MergeError::VestsDifferently));
+    assert_eq!(a.total_deposited(), 100);
+
+    // A projection skips the merge instead.
+    let plan = vec![(
+        10,
+        EventKind::Merge {
+            other: Box::new(other),
+        },
+    )];
+    let samples: Vec<_> = a.project_with(0, 10, 10, plan).collect();
+    assert_eq!(samples[1], (10, 50, 50));
+}
+
+#[test]
+fn early_withdrawal_with_penalty() {
+    clock_reset(0);
+    let policy = PenaltyPolicy::new(2_500, ForfeitDestination::Pool);
+    let mut b = TokenStream::new_from_half_life(HalfLife::seconds(10)).with_event_log();
+    b.deposit_at(0, 1_000).unwrap();
+    assert_eq!(b.claim_at(10), Ok(500));
+
+    assert_eq!(b.early_withdraw_at(10, 400, policy), Ok((300, 100)));
+    assert_eq!(b.balance_still_vesting_at(10), Ok(100));
+    assert_eq!(b.balance_claimable_at(10), Ok(0));
+    assert_eq!(b.total_deposited(), 900);
+    assert_eq!(b.total_claimed(), 800);
+    assert_eq!(b.balance_still_vesting_at(20), Ok(50));
+    assert_eq!(b.check_invariants_at(20), Ok(()));
+    assert_eq!(
+        b.early_withdraw_at(20, 51, policy),
+        Err(WithdrawError::InsufficientStillVesting {
+            requested: 51,
+            still_vesting: 50
+        })
+    );
+    b.pause_at(20).unwrap();
+    assert_eq!(
+        b.early_withdraw_at(20, 1, policy),
+        Err(WithdrawError::Paused)
+    );
+
+    let replayed = TokenStream::replay(b.event_log().unwrap().events()).unwrap();
+    assert_eq!(replayed.snapshot(), b.snapshot());
+
+    // Forfeits meant for a pool go to its remaining holders.
+    let mut pool = RewardPool::new_from_half_life(HalfLife::seconds(10));
+    assert_eq!(policy.route(100, Some(&mut pool)), Err(NoShareholders));
+    pool.set_shares("alice", 1);
+    pool.set_shares("bob", 3);
+    let mut d = TokenStream::new_from_half_life(HalfLife::seconds(10));
+    d.deposit_at(0, 400).unwrap();
+    let (_, forfeited) = d.early_withdraw_at(0, 400, policy).unwrap();
+    assert_eq!(
+        policy.route(forfeited, Some(&mut pool)),
+        Ok(Routed::Pool(100))
+    );
+    assert_eq!(pool.total_distributed(), 100);
+    assert_eq!(pool.balance_still_vesting(&"bob"), 75);
+    assert_eq!(
+        policy.route::<&str, ThreadClock>(forfeited, None),
+        Err(NoShareholders)
+    );
+
+    // Other destinations need no pool and leave any given one alone.
+    let burn = PenaltyPolicy::new(2_500, ForfeitDestination::Burn);
+    assert_eq!(
+        burn.route(forfeited, Some(&mut pool)),
+        Ok(Routed::Burn(100))
+    );
+    assert_eq!(pool.total_distributed(), 100);
+    let treasury = PenaltyPolicy::new(2_500, ForfeitDestination::Treasury);
+    assert_eq!(
+        treasury.route::<&str, ThreadClock>(forfeited, None),
+        Ok(Routed::Treasury(100))
+    );
+
+    let free = PenaltyPolicy::new(0, ForfeitDestination::Burn);
+    let mut c = TokenStream::new(0.0);
+    c.deposit_at(0, 7).unwrap();
+    assert_eq!(c.early_withdraw_at(0, 7, free), Ok((7, 0)));
+    assert_eq!(policy.forfeit(u128::MAX), u128::MAX / 4);
+}
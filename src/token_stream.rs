@@ -1,47 +1,393 @@
 #![allow(dead_code)]
 
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::Bound;
+
 mod clock;
+mod curve;
+mod decay;
+mod error;
+mod event;
+mod history;
+mod multi_rate;
+mod penalty;
+mod pool;
+mod projection;
+mod registry;
+mod snapshot;
 pub use clock::*;
+pub use curve::*;
+pub use decay::*;
+pub use error::*;
+pub use event::*;
+pub use history::*;
+pub use multi_rate::*;
+pub use penalty::*;
+pub use pool::*;
+pub use projection::*;
+pub use registry::*;
+pub use snapshot::*;
 
+/// A continuously vesting bucket, reading the current time from `C`.
 #[derive(Default)]
-pub struct TokenStream {
-    decay_rate_per_second: f64,
+pub struct TokenStream<C = ThreadClock> {
+    curve: Curve,
+    rounding: RoundingMode,
     total_deposited: u128, // Cumulative
     total_claimed: u128,   // Cumulative
 
     last_update_principal: u128,
     last_update_timestamp: u64,
+
+    // Start of the current decay curve. Only deposits, tranche unlocks and rate changes move
+    // it, so settling or claiming more or less often never changes how much vests.
+    origin_principal: u128,
+    origin_timestamp: u64,
+
+    // Nothing vests for `cliff` seconds after the first deposit, which sets `cliff_end`. Kept
+    // even if everything deposited is later taken back, so the cliff never restarts.
+    cliff: u64,
+    cliff_end: Option<u64>,
+
+    // In tranche mode each deposit is locked for `tranche_lock` seconds, then joins the curve.
+    // Locked amounts by unlock time.
+    tranche_lock: u64,
+    tranches: BTreeMap<u64, u128>,
+
+    // Future half-life changes by the time they take effect.
+    rate_schedule: BTreeMap<u64, HalfLife>,
+
+    // While paused, balances stay as they were at this time.
+    paused_at: Option<u64>,
+
+    log: Option<EventLog>,
+    clock: C,
 }
 
 impl TokenStream {
-    //const SECONDS_PER_DAY: f64 = 86_400.0;
+    /// Construct with a half-life and automatically compute the rate.
+    pub fn new_from_half_life(half_life: HalfLife) -> Self {
+        Self {
+            curve: ExponentialCurve(DecayRate::from_half_life(half_life)).into(),
+            ..Default::default()
+        }
+    }
 
-    fn decay_rate_from_half_life(days: f64) -> f64 {
-        std::f64::consts::LN_2 / (days) // To use seconds: (days * Self::SECONDS_PER_DAY)
+    pub fn new(decay_rate_per_second: f64) -> Self {
+        Self {
+            curve: ExponentialCurve(DecayRate::from_f64(decay_rate_per_second)).into(),
+            ..Default::default()
+        }
     }
 
-    /// Construct with half-life (in days) and automatically compute the rate.
-    pub fn new_from_half_life(days: f64) -> Self {
-        Self::new(Self::decay_rate_from_half_life(days))
+    /// Construct with an exact fixed-point rate.
+    pub fn from_decay_rate(decay_rate_per_second: DecayRate) -> Self {
+        Self::from_curve(ExponentialCurve(decay_rate_per_second))
     }
 
-    pub fn new(decay_rate_per_second: f64) -> Self {
+    /// Construct with any supported vesting curve.
+    pub fn from_curve(curve: impl Into<Curve>) -> Self {
         Self {
-            decay_rate_per_second,
+            curve: curve.into(),
             ..Default::default()
         }
     }
 
-    /// Change half-life. Settles first to preserve continuity.
-    pub fn set_half_life(&mut self, days: f64) {
-        self.settle();
-        self.decay_rate_per_second = Self::decay_rate_from_half_life(days);
+    /// Rebuild a stream from a snapshot of any supported version.
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self, SnapshotError> {
+        let snapshot = snapshot.migrate()?;
+        Ok(Self {
+            curve: snapshot.curve,
+            rounding: snapshot.rounding,
+            total_deposited: snapshot.total_deposited,
+            total_claimed: snapshot.total_claimed,
+            last_update_principal: snapshot.last_update_principal,
+            last_update_timestamp: snapshot.last_update_timestamp,
+            origin_principal: snapshot.origin_principal,
+            origin_timestamp: snapshot.origin_timestamp,
+            cliff: snapshot.cliff,
+            cliff_end: snapshot.cliff_end,
+            tranche_lock: snapshot.tranche_lock,
+            tranches: snapshot.tranches.into_iter().collect(),
+            rate_schedule: snapshot.rate_schedule.into_iter().collect(),
+            paused_at: snapshot.paused_at,
+            log: None,
+            clock: ThreadClock,
+        })
+    }
+
+    /// Rebuild a stream from an event log alone, checking each recorded state on the way.
+    /// The result keeps logging, starting with a copy of `events`.
+    pub fn replay(events: &[Event]) -> Result<Self, ReplayError> {
+        let Some((open, rest)) = events.split_first() else {
+            return Err(ReplayError::MissingOpen);
+        };
+        if open.kind != EventKind::Open {
+            return Err(ReplayError::MissingOpen);
+        }
+        let mut b = Self::from_snapshot(open.state.clone())
+            .map_err(ReplayError::Snapshot)?
+            .with_event_log();
+        for (index, event) in rest.iter().enumerate().map(|(i, e)| (i + 1, e)) {
+            let t = event.timestamp;
+            let applied = match event.kind.clone() {
+                EventKind::Open => Err(()),
+                EventKind::Deposit { amount } => b.deposit_at(t, amount).map_err(drop),
+                EventKind::Claim { amount } => b.claim_amount_at(t, amount).map(drop).map_err(drop),
+                EventKind::Settle => b.settle_at(t).map(drop).map_err(drop),
+                EventKind::SetHalfLife { half_life } => {
+                    b.set_half_life_at(t, half_life).map_err(drop)
+                }
+                EventKind::ScheduleHalfLife { at, half_life } => {
+                    b.schedule_half_life_at(t, at, half_life).map_err(drop)
+                }
+                EventKind::Clawback { amount } => b.clawback_at(t, amount).map(drop).map_err(drop),
+                EventKind::Pause => b.pause_at(t).map_err(drop),
+                EventKind::Resume => b.resume_at(t).map_err(drop),
+                EventKind::Split { amount } => b.split_at(t, amount).map(drop).map_err(drop),
+                EventKind::EarlyWithdraw { amount, policy } => b
+                    .early_withdraw_at(t, amount, policy)
+                    .map(drop)
+                    .map_err(drop),
+                EventKind::Merge { other } => Self::from_snapshot(*other)
+                    .map_err(drop)
+                    .and_then(|other| b.merge_at(t, other).map_err(drop)),
+            };
+            applied.map_err(|()| ReplayError::Rejected { index })?;
+            if b.snapshot() != event.state {
+                return Err(ReplayError::Mismatch { index });
+            }
+        }
+        Ok(b)
+    }
+
+    /// Decode the compact binary form written by [`TokenStream::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        Self::from_snapshot(Snapshot::from_bytes(bytes)?)
+    }
+}
+
+impl<C: Clock> TokenStream<C> {
+    /// Replace the time source, keeping all accounting state.
+    pub fn with_clock<D: Clock>(self, clock: D) -> TokenStream<D> {
+        TokenStream {
+            curve: self.curve,
+            rounding: self.rounding,
+            total_deposited: self.total_deposited,
+            total_claimed: self.total_claimed,
+            last_update_principal: self.last_update_principal,
+            last_update_timestamp: self.last_update_timestamp,
+            origin_principal: self.origin_principal,
+            origin_timestamp: self.origin_timestamp,
+            cliff: self.cliff,
+            cliff_end: self.cliff_end,
+            tranche_lock: self.tranche_lock,
+            tranches: self.tranches,
+            rate_schedule: self.rate_schedule,
+            paused_at: self.paused_at,
+            log: self.log,
+            clock,
+        }
+    }
+
+    /// Choose how every amount still vesting is rounded. Meant to be set at construction.
+    pub fn with_rounding(self, rounding: RoundingMode) -> Self {
+        Self { rounding, ..self }
+    }
+
+    pub fn rounding(&self) -> RoundingMode {
+        self.rounding
+    }
+
+    /// Lock everything for `cliff` seconds after the first deposit. Deposits made during the
+    /// cliff all start vesting when it ends; later deposits vest immediately. Meant to be set at
+    /// construction.
+    pub fn with_cliff(self, cliff: u64) -> Self {
+        Self { cliff, ..self }
+    }
+
+    pub fn cliff(&self) -> u64 {
+        self.cliff
+    }
+
+    /// When the cliff ends, once the first deposit has started it.
+    pub fn cliff_end(&self) -> Option<u64> {
+        self.cliff_end
+    }
+
+    /// Tranche mode: lock each deposit for `lock` seconds from its own deposit time, after which
+    /// it joins the curve by "snapshot, then add". Meant to be set at construction.
+    pub fn with_tranche_lock(self, lock: u64) -> Self {
+        Self {
+            tranche_lock: lock,
+            ..self
+        }
+    }
+
+    pub fn tranche_lock(&self) -> u64 {
+        self.tranche_lock
+    }
+
+    /// Record every following operation, starting with an [`EventKind::Open`] event.
+    pub fn with_event_log(mut self) -> Self {
+        let mut log = EventLog::default();
+        log.push(Event {
+            timestamp: self.last_update_timestamp,
+            kind: EventKind::Open,
+            state: self.snapshot(),
+        });
+        self.log = Some(log);
+        self
+    }
+
+    /// The events recorded so far, if logging is enabled.
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.log.as_ref()
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// The clock, for advancing a [`TestClock`] the stream owns.
+    pub fn clock_mut(&mut self) -> &mut C {
+        &mut self.clock
+    }
+
+    /// Capture the accounting state for persistence.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            decay_rate: self.curve.decay_rate().unwrap_or_default(),
+            curve: self.curve,
+            rounding: self.rounding,
+            total_deposited: self.total_deposited,
+            total_claimed: self.total_claimed,
+            last_update_principal: self.last_update_principal,
+            last_update_timestamp: self.last_update_timestamp,
+            origin_principal: self.origin_principal,
+            origin_timestamp: self.origin_timestamp,
+            cliff: self.cliff,
+            cliff_end: self.cliff_end,
+            tranche_lock: self.tranche_lock,
+            tranches: self.tranches.iter().map(|(&t, &a)| (t, a)).collect(),
+            rate_schedule: self.rate_schedule.iter().map(|(&t, &h)| (t, h)).collect(),
+            paused_at: self.paused_at,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.snapshot().to_bytes()
+    }
+
+    /// Change half-life. Settles first to preserve continuity. Other curves are replaced by
+    /// exponential decay.
+    pub fn set_half_life(&mut self, half_life: HalfLife) {
+        self.set_half_life_unchecked(self.now(), half_life);
+    }
+
+    /// Change half-life at `t`.
+    pub fn set_half_life_at(&mut self, t: u64, half_life: HalfLife) -> Result<(), TimeRegression> {
+        self.check_time(t)?;
+        self.set_half_life_unchecked(t, half_life);
+        Ok(())
+    }
+
+    /// Current half-life, converted back from the per-second rate.
+    pub fn half_life(&self) -> HalfLife {
+        self.decay_rate().half_life()
+    }
+
+    /// Change half-life at `at`, without anyone having to call [`TokenStream::set_half_life`]
+    /// then. Balances follow each rate in turn across the change.
+    pub fn schedule_half_life(
+        &mut self,
+        at: u64,
+        half_life: HalfLife,
+    ) -> Result<(), TimeRegression> {
+        self.schedule_half_life_at(self.now(), at, half_life)
+    }
+
+    /// Schedule a half-life change at `at`, as of `t`. The change must not be in the past.
+    pub fn schedule_half_life_at(
+        &mut self,
+        t: u64,
+        at: u64,
+        half_life: HalfLife,
+    ) -> Result<(), TimeRegression> {
+        self.check_time(t)?;
+        if at < t {
+            return Err(TimeRegression { at, last_update: t });
+        }
+        let before = self.debug_vested(t);
+        self.settle_unchecked(t);
+        self.rate_schedule.insert(at, half_life);
+        self.debug_check_vested(t, before, 0);
+        self.record(t, EventKind::ScheduleHalfLife { at, half_life });
+        Ok(())
+    }
+
+    /// Half-life changes still to come, in order.
+    pub fn scheduled_half_lives(&self) -> impl Iterator<Item = (u64, HalfLife)> + '_ {
+        let now = self.vesting_time(self.now());
+        self.rate_schedule
+            .range((Bound::Excluded(now), Bound::Unbounded))
+            .map(|(&at, &half_life)| (at, half_life))
+    }
+
+    /// The exponential decay rate, or zero for other curves.
+    pub fn decay_rate(&self) -> DecayRate {
+        self.curve().decay_rate().unwrap_or_default()
+    }
+
+    /// The curve in effect now, including scheduled rate changes.
+    pub fn curve(&self) -> Curve {
+        self.origin_at(self.vesting_time(self.now())).2
+    }
+
+    /// Freeze the stream: balances stop vesting and claims are refused until
+    /// [`TokenStream::resume`]. Deposits are still accepted, and wait with everything else.
+    pub fn pause(&mut self) {
+        self.pause_unchecked(self.now());
+    }
+
+    pub fn pause_at(&mut self, t: u64) -> Result<(), TimeRegression> {
+        self.check_time(t)?;
+        self.pause_unchecked(t);
+        Ok(())
+    }
+
+    /// Continue vesting from where [`TokenStream::pause`] left off, as if the paused interval
+    /// never happened. Pending tranche unlocks, scheduled rate changes and the end of the cliff
+    /// are pushed back by its length.
+    pub fn resume(&mut self) {
+        self.resume_unchecked(self.now());
+    }
+
+    pub fn resume_at(&mut self, t: u64) -> Result<(), TimeRegression> {
+        self.check_time(t)?;
+        self.resume_unchecked(t);
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
     }
 
     /// Total vested since inception, regardless of whether it was claimed.
     pub fn total_vested(&self) -> u128 {
+        self.vested_at(self.now())
+    }
+
+    /// Total vested since inception as of `t`.
+    pub fn total_vested_at(&self, t: u64) -> Result<u128, TimeRegression> {
+        self.check_time(t).map(|()| self.vested_at(t))
+    }
+
+    /// Total amount deposited since inception.
+    pub fn total_deposited(&self) -> u128 {
         self.total_deposited
-            .saturating_sub(self.balance_still_vesting())
     }
 
     /// Total amount claimed since inception.
@@ -49,50 +395,851 @@ impl TokenStream {
         self.total_claimed
     }
 
-    /// Amount you could claim *right now*.
+    /// Amount you could claim *right now*. Still reported while paused, though it can only be
+    /// claimed after resuming.
     pub fn balance_claimable(&self) -> u128 {
-        let rel = self.total_vested();
-        rel.saturating_sub(self.total_claimed)
+        self.claimable_at(self.now())
+    }
+
+    /// Amount you could claim at `t`.
+    pub fn balance_claimable_at(&self, t: u64) -> Result<u128, TimeRegression> {
+        self.check_time(t).map(|()| self.claimable_at(t))
     }
 
-    /// The amount that has yet to fully vest (rounds down). Continuously decays.
+    /// The amount that has yet to fully vest, rounded per [`TokenStream::rounding`]. Continuously decays.
     pub fn balance_still_vesting(&self) -> u128 {
-        if self.last_update_principal == 0 {
-            return 0;
-        }
-        let dt = now().saturating_sub(self.last_update_timestamp) as f64;
-        let factor = (-self.decay_rate_per_second * dt).exp();
-        ((self.last_update_principal as f64) * factor).floor() as u128
+        self.principal_at(self.now())
+    }
+
+    /// The amount that has yet to fully vest at `t`.
+    pub fn balance_still_vesting_at(&self, t: u64) -> Result<u128, TimeRegression> {
+        self.check_time(t).map(|()| self.principal_at(t))
     }
 
     /// Snapshot current remaining and reset timestamp. Returns the current amount still vesting.
+    /// The decay curve itself is untouched, so settling never changes future balances.
     pub fn settle(&mut self) -> u128 {
-        let p_now = self.balance_still_vesting();
-        self.last_update_principal = p_now;
-        self.last_update_timestamp = now();
+        let t = self.now();
+        let p_now = self.settle_unchecked(t);
+        self.record(t, EventKind::Settle);
         p_now
     }
 
+    /// Snapshot the remaining amount at `t` and move the timestamp there.
+    pub fn settle_at(&mut self, t: u64) -> Result<u128, TimeRegression> {
+        self.check_time(t)?;
+        let p_now = self.settle_unchecked(t);
+        self.record(t, EventKind::Settle);
+        Ok(p_now)
+    }
+
     /// Deposit `amount` into the bucket
     pub fn deposit(&mut self, amount: u128) {
-        let p_now = self.settle();
-        self.last_update_principal = p_now.saturating_add(amount);
-        self.total_deposited = self.total_deposited.saturating_add(amount);
+        self.deposit_unchecked(self.now(), amount);
+    }
+
+    /// Deposit `amount` into the bucket at `t`.
+    pub fn deposit_at(&mut self, t: u64, amount: u128) -> Result<(), TimeRegression> {
+        self.check_time(t)?;
+        self.deposit_unchecked(t, amount);
+        Ok(())
     }
 
-    /// Claim everything currently claimable; returns the claimed amount.
+    /// Claim everything currently claimable; returns the claimed amount. Claims nothing while
+    /// paused, which [`TokenStream::checked_claim`] reports as an error instead.
     pub fn claim(&mut self) -> u128 {
-        let p_now = self.settle();
+        self.claim_unchecked(self.now())
+    }
+
+    /// Claim everything claimable at `t`; returns the claimed amount, zero while paused.
+    pub fn claim_at(&mut self, t: u64) -> Result<u128, TimeRegression> {
+        self.check_time(t)?;
+        Ok(self.claim_unchecked(t))
+    }
+
+    /// Claim exactly `amount`, leaving the rest claimable. Fails without side effects if
+    /// `amount` exceeds [`TokenStream::balance_claimable`].
+    pub fn claim_amount(&mut self, amount: u128) -> Result<u128, ClaimError> {
+        self.claim_amount_at(self.now(), amount)
+    }
+
+    /// Claim exactly `amount` at `t`.
+    pub fn claim_amount_at(&mut self, t: u64, amount: u128) -> Result<u128, ClaimError> {
+        self.check_time(t)?;
+        if self.is_paused() {
+            return Err(ClaimError::Paused);
+        }
+        let claimable = self.claimable_at(t);
+        if amount > claimable {
+            return Err(ClaimError::InsufficientClaimable {
+                requested: amount,
+                claimable,
+            });
+        }
+        let before = self.debug_vested(t);
+        self.settle_unchecked(t);
+        self.total_claimed += amount;
+        self.debug_check_vested(t, before, 0);
+        self.record(t, EventKind::Claim { amount });
+        Ok(amount)
+    }
+
+    /// Revoke the grant: take back everything still vesting, leaving what has vested to the
+    /// beneficiary. Returns the amount taken back, which no longer counts as deposited.
+    pub fn revoke(&mut self) -> u128 {
+        self.clawback(u128::MAX)
+    }
+
+    pub fn revoke_at(&mut self, t: u64) -> Result<u128, TimeRegression> {
+        self.clawback_at(t, u128::MAX)
+    }
+
+    /// Take back up to `amount` of what is still vesting, starting with the tranches furthest
+    /// from unlocking. What remains keeps its schedule. Returns the amount taken back.
+    pub fn clawback(&mut self, amount: u128) -> u128 {
+        self.clawback_unchecked(self.now(), amount)
+    }
+
+    pub fn clawback_at(&mut self, t: u64, amount: u128) -> Result<u128, TimeRegression> {
+        self.check_time(t)?;
+        Ok(self.clawback_unchecked(t, amount))
+    }
+
+    /// Exit early with `amount` of what is still vesting. The penalty is forfeited and the rest
+    /// is paid out, counting as vested and claimed. Returns `(paid, forfeited)`. Like claims,
+    /// refused while paused.
+    pub fn early_withdraw(
+        &mut self,
+        amount: u128,
+        policy: PenaltyPolicy,
+    ) -> Result<(u128, u128), WithdrawError> {
+        self.early_withdraw_at(self.now(), amount, policy)
+    }
+
+    pub fn early_withdraw_at(
+        &mut self,
+        t: u64,
+        amount: u128,
+        policy: PenaltyPolicy,
+    ) -> Result<(u128, u128), WithdrawError> {
+        self.check_time(t)?;
+        if self.is_paused() {
+            return Err(WithdrawError::Paused);
+        }
+        let still_vesting = self.principal_at(t);
+        if amount > still_vesting {
+            return Err(WithdrawError::InsufficientStillVesting {
+                requested: amount,
+                still_vesting,
+            });
+        }
+        let forfeited = policy.forfeit(amount);
+        let Some(paid) = amount.checked_sub(forfeited) else {
+            return Err(WithdrawError::InvalidPenalty(InvalidPenalty {
+                penalty_bps: policy.penalty_bps(),
+            }));
+        };
+        let before = self.debug_vested(t);
+        self.take_principal(t, amount);
+        self.total_deposited -= forfeited;
+        self.total_claimed += paid;
+        self.settle_unchecked(t);
+        self.debug_check_vested(t, before, forfeited);
+        self.record(t, EventKind::EarlyWithdraw { amount, policy });
+        Ok((paid, forfeited))
+    }
+
+    /// Carve `amount` of what is still vesting out into a new stream for another beneficiary,
+    /// with the same curve and timing. Tranches furthest from unlocking go first. What has
+    /// vested is unchanged at the split; afterwards the two parts round separately, so together
+    /// they can vest up to a unit more than the whole would have.
+    pub fn split(&mut self, amount: u128) -> Result<Self, SplitError>
+    where
+        C: Clone,
+    {
+        self.split_at(self.now(), amount)
+    }
+
+    pub fn split_at(&mut self, t: u64, amount: u128) -> Result<Self, SplitError>
+    where
+        C: Clone,
+    {
+        self.check_time(t)?;
+        let still_vesting = self.principal_at(t);
+        if amount > still_vesting {
+            return Err(SplitError::InsufficientStillVesting {
+                requested: amount,
+                still_vesting,
+            });
+        }
+        Ok(self.split_unchecked(t, amount))
+    }
+
+    /// Split off `numerator / denominator` of what is still vesting, rounded down. Fails with
+    /// [`SplitError::InvalidFraction`] on a zero denominator or a fraction above one.
+    pub fn split_fraction(&mut self, numerator: u64, denominator: u64) -> Result<Self, SplitError>
+    where
+        C: Clone,
+    {
+        self.split_fraction_at(self.now(), numerator, denominator)
+    }
+
+    pub fn split_fraction_at(
+        &mut self,
+        t: u64,
+        numerator: u64,
+        denominator: u64,
+    ) -> Result<Self, SplitError>
+    where
+        C: Clone,
+    {
+        self.check_time(t)?;
+        if denominator == 0 || numerator > denominator {
+            return Err(SplitError::InvalidFraction {
+                numerator,
+                denominator,
+            });
+        }
+        let amount = FixedAmount::from_whole(self.principal_at(t))
+            .mul_ratio(numerator, denominator)
+            .floor();
+        Ok(self.split_unchecked(t, amount))
+    }
+
+    /// Combine `other` into this stream. Both must vest the same way from now on: the same
+    /// curve, rounding, pending rate changes, tranche lock and pause state, cliffs that end
+    /// together or have both ended, and for linear and step curves the same start. Nothing
+    /// changes on error. Vested, claimed and deposited
+    /// totals add up exactly at the merge.
+    pub fn merge(&mut self, other: Self) -> Result<(), MergeError> {
+        let t = self.now().max(other.last_update_timestamp);
+        self.merge_at(t, other)
+    }
+
+    /// Like [`TokenStream::merge`], at `t`, which must not precede either stream's last update.
+    pub fn merge_at(&mut self, t: u64, other: Self) -> Result<(), MergeError> {
+        self.check_time(t)?;
+        other.check_time(t)?;
+        if !self.vests_like(&other, t) {
+            return Err(MergeError::VestsDifferently);
+        }
+        self.merge_unchecked(t, other);
+        Ok(())
+    }
+
+    /// Deposits still locked in tranches. Included in [`TokenStream::balance_still_vesting`].
+    pub fn balance_locked(&self) -> u128 {
+        self.locked_at(self.now())
+    }
+
+    pub fn balance_locked_at(&self, t: u64) -> Result<u128, TimeRegression> {
+        self.check_time(t)?;
+        Ok(self.locked_at(t))
+    }
+
+    /// Total still unclaimed.
+    pub fn unclaimed_total(&self) -> u128 {
+        self.total_deposited.saturating_sub(self.total_claimed)
+    }
+
+    /// Seconds until at least `amount` is claimable, assuming no further deposits or claims.
+    /// Exact for the rounded balances this stream reports. `None` if that never happens, e.g.
+    /// when `amount` exceeds [`TokenStream::unclaimed_total`] or the stream is paused, since
+    /// nothing can be claimed until it resumes.
+    pub fn time_until_claimable(&self, amount: u128) -> Option<u64> {
+        if amount > self.unclaimed_total() || self.is_paused() {
+            return None;
+        }
+        self.seconds_until(|b, t| b.claimable_at(t) >= amount)
+    }
+
+    /// Seconds until less than `amount` is still vesting, assuming no further deposits.
+    /// `None` if that never happens, e.g. for an `amount` of zero.
+    pub fn time_until_still_vesting_below(&self, amount: u128) -> Option<u64> {
+        self.seconds_until(|b, t| b.principal_at(t) < amount)
+    }
+
+    /// Sample the stream every `step` seconds from `from` to `to`, assuming no further
+    /// operations.
+    pub fn project(&self, from: u64, to: u64, step: u64) -> Projection {
+        self.project_with(from, to, step, Vec::new())
+    }
+
+    /// Like [`TokenStream::project`], with planned operations applied along the way. They take
+    /// effect before a sample at the same time, and planned claims take at most what is
+    /// claimable.
+    pub fn project_with(
+        &self,
+        from: u64,
+        to: u64,
+        step: u64,
+        plan: Vec<(u64, EventKind)>,
+    ) -> Projection {
+        let stream = TokenStream::from_snapshot(self.snapshot()).expect("current snapshot");
+        Projection::new(stream, from, to, step, plan)
+    }
+
+    /// Verify conservation (`deposited = claimed + still_vesting + claimable`, with
+    /// `still_vesting` rounded per [`TokenStream::rounding`]), that
+    /// `total_vested` has not decreased since the last update, and that
+    /// `last_update_principal <= total_deposited - total_claimed`.
+    pub fn check_invariants(&self) -> Result<(), InvariantReport> {
+        self.report_at(self.now())
+    }
+
+    /// Verify the invariants as of `t`.
+    pub fn check_invariants_at(&self, t: u64) -> Result<(), InvariantCheckError> {
+        self.check_time(t)?;
+        Ok(self.report_at(t)?)
+    }
+
+    /// Like [`TokenStream::deposit`], but fails instead of saturating.
+    pub fn checked_deposit(&mut self, amount: u128) -> Result<(), TokenStreamError> {
+        self.checked_deposit_at(self.now(), amount)
+    }
+
+    /// Like [`TokenStream::deposit_at`], but fails instead of saturating. Nothing changes on error.
+    pub fn checked_deposit_at(&mut self, t: u64, amount: u128) -> Result<(), TokenStreamError> {
+        self.check_time(t)?;
+        self.checked_principal_at(t)?;
+        let principal = self.principal_at(t).checked_add(amount);
+        let deposited = self.total_deposited.checked_add(amount);
+        if principal.is_none() || deposited.is_none() {
+            return Err(TokenStreamError::Overflow);
+        }
+        self.deposit_unchecked(t, amount);
+        Ok(())
+    }
+
+    /// Like [`TokenStream::claim`], but fails on an inconsistent state instead of clamping, and
+    /// while paused instead of claiming nothing.
+    pub fn checked_claim(&mut self) -> Result<u128, TokenStreamError> {
+        self.checked_claim_at(self.now())
+    }
+
+    /// Like [`TokenStream::claim_at`], but fails on an inconsistent state instead of clamping, and
+    /// while paused instead of claiming nothing.
+    pub fn checked_claim_at(&mut self, t: u64) -> Result<u128, TokenStreamError> {
+        self.check_time(t)?;
+        if self.is_paused() {
+            return Err(TokenStreamError::Paused);
+        }
+        self.checked_principal_at(t)?;
+        Ok(self.claim_unchecked(t))
+    }
+
+    /// Like [`TokenStream::total_vested`], but fails on an inconsistent state.
+    pub fn checked_total_vested(&self) -> Result<u128, TokenStreamError> {
+        let p_now = self.checked_principal_at(self.now())?;
+        Ok(self.total_deposited - p_now)
+    }
+
+    /// Like [`TokenStream::unclaimed_total`], but fails on an inconsistent state.
+    pub fn checked_unclaimed_total(&self) -> Result<u128, TokenStreamError> {
+        self.checked_principal_at(self.now())?;
+        Ok(self.total_deposited - self.total_claimed)
+    }
+
+    /// The clock's current time. A clock running behind the last update counts as no time passing.
+    fn now(&self) -> u64 {
+        self.clock.now().max(self.last_update_timestamp)
+    }
+
+    /// The least number of seconds from now at which `reached` holds, which must stay true
+    /// once it is.
+    fn seconds_until(&self, reached: impl Fn(&Self, u64) -> bool) -> Option<u64> {
+        let now = self.now();
+        if reached(self, now) {
+            return Some(0);
+        }
+        let max = u64::MAX - now;
+        // Grow the window until it contains the answer, then bisect it.
+        let (mut lo, mut hi) = (0, 1);
+        while !reached(self, now + hi) {
+            if hi == max {
+                return None;
+            }
+            lo = hi;
+            hi = hi.saturating_mul(2).min(max);
+        }
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            if reached(self, now + mid) {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Some(hi)
+    }
+
+    fn check_time(&self, t: u64) -> Result<(), TimeRegression> {
+        if t < self.last_update_timestamp {
+            return Err(TimeRegression {
+                at: t,
+                last_update: self.last_update_timestamp,
+            });
+        }
+        Ok(())
+    }
+
+    /// The principal at `t`, after verifying that the stored totals are consistent.
+    fn checked_principal_at(&self, t: u64) -> Result<u128, InvariantViolation> {
+        match self.violations_at(t).first() {
+            Some(&violation) => Err(violation),
+            None => Ok(self.principal_at(t)),
+        }
+    }
+
+    fn report_at(&self, t: u64) -> Result<(), InvariantReport> {
+        let violations = self.violations_at(t);
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(InvariantReport { violations })
+        }
+    }
+
+    fn violations_at(&self, t: u64) -> Vec<InvariantViolation> {
+        let mut violations = Vec::new();
+        let p_now = self.principal_at(t);
+        let vested = self.total_deposited.saturating_sub(p_now);
+        if self.total_claimed > vested {
+            violations.push(InvariantViolation::ClaimedExceedsVested {
+                claimed: self.total_claimed,
+                vested,
+            });
+        }
+        if p_now > self.last_update_principal {
+            violations.push(InvariantViolation::VestedDecreased {
+                before: self
+                    .total_deposited
+                    .saturating_sub(self.last_update_principal),
+                after: vested,
+            });
+        }
+        let unclaimed = self.total_deposited.saturating_sub(self.total_claimed);
+        if self.last_update_principal > unclaimed {
+            violations.push(InvariantViolation::PrincipalExceedsUnclaimed {
+                principal: self.last_update_principal,
+                unclaimed,
+            });
+        }
+        violations
+    }
+
+    /// Invariants must hold after every mutation; checked in debug builds only.
+    fn debug_check_invariants(&self) {
+        debug_assert_eq!(self.report_at(self.last_update_timestamp), Ok(()));
+    }
+
+    /// Total vested at `t` before a mutation, taken in debug builds only.
+    fn debug_vested(&self, t: u64) -> Option<u128> {
+        cfg!(debug_assertions).then(|| self.vested_at(t))
+    }
+
+    /// A mutation at `t` must not lower total vested by more than the `removed` amount it took
+    /// out of the stream. Also checks the invariants.
+    fn debug_check_vested(&self, t: u64, before: Option<u128>, removed: u128) {
+        if let Some(before) = before {
+            let after = self.vested_at(t);
+            debug_assert!(
+                after.saturating_add(removed) >= before,
+                "total vested decreased from {before} to {after}"
+            );
+        }
+        self.debug_check_invariants();
+    }
+
+    fn principal_at(&self, t: u64) -> u128 {
+        let t = self.vesting_time(t);
+        let (origin, from, curve) = self.origin_at(t);
+        self.decay_from(curve, origin, from, t)
+            .saturating_add(self.locked_at(t))
+    }
+
+    /// `principal` vesting along `curve` from `from` to `t`, after the cliff.
+    fn decay_from(&self, curve: Curve, principal: u128, from: u64, t: u64) -> u128 {
+        let dt = t.saturating_sub(from.max(self.cliff_end.unwrap_or_default()));
+        let principal = FixedAmount::from_whole(principal);
+        curve.still_vesting(principal, dt).round(self.rounding)
+    }
+
+    /// The start of the curve in effect at `t` and the curve itself, once every tranche unlocked
+    /// and every rate change scheduled by then has taken effect, in time order.
+    fn origin_at(&self, t: u64) -> (u128, u64, Curve) {
+        let mut unlocks = self.tranches.range(..=t).peekable();
+        let mut changes = self.rate_schedule.range(..=t).peekable();
+        let (mut principal, mut from, mut curve) =
+            (self.origin_principal, self.origin_timestamp, self.curve);
+        loop {
+            let unlock = unlocks.peek().map(|&(&at, _)| at);
+            let change = changes.peek().map(|&(&at, _)| at);
+            let at = match (unlock, change) {
+                (Some(u), Some(c)) => u.min(c),
+                (Some(at), None) | (None, Some(at)) => at,
+                (None, None) => return (principal, from, curve),
+            };
+            principal = self.decay_from(curve, principal, from, at);
+            from = at;
+            if unlock == Some(at) {
+                let (_, &amount) = unlocks.next().expect("peeked");
+                principal = principal.saturating_add(amount);
+            }
+            if change == Some(at) {
+                let (_, &half_life) = changes.next().expect("peeked");
+                curve = ExponentialCurve(DecayRate::from_half_life(half_life)).into();
+            }
+        }
+    }
+
+    /// Where `t` falls on the vesting timeline, which stands still while paused.
+    fn vesting_time(&self, t: u64) -> u64 {
+        self.paused_at.map_or(t, |paused_at| t.min(paused_at))
+    }
+
+    fn locked_at(&self, t: u64) -> u128 {
+        self.tranches
+            .range((Bound::Excluded(t), Bound::Unbounded))
+            .fold(0, |locked, (_, &amount)| locked.saturating_add(amount))
+    }
+
+    fn vested_at(&self, t: u64) -> u128 {
+        self.total_deposited.saturating_sub(self.principal_at(t))
+    }
+
+    fn claimable_at(&self, t: u64) -> u128 {
+        self.vested_at(t).saturating_sub(self.total_claimed)
+    }
+
+    fn settle_unchecked(&mut self, t: u64) -> u128 {
+        self.apply_due(self.vesting_time(t));
+        let p_now = self.principal_at(t);
+        self.last_update_principal = p_now;
+        self.last_update_timestamp = t;
+        self.debug_check_invariants();
+        p_now
+    }
+
+    /// Credit `deposited` at `t`, of which only `still_vesting` joins the decay curve; the rest
+    /// has already vested. Used by pools that accrue rewards lazily.
+    pub(crate) fn accrue_at(&mut self, t: u64, deposited: u128, still_vesting: u128) {
+        debug_assert!(still_vesting <= deposited);
+        let before = self.debug_vested(t);
+        let vt = self.vesting_time(t);
+        if deposited > 0 {
+            self.start_cliff(vt);
+        }
+        if still_vesting > 0 && self.tranche_lock > 0 {
+            let locked = self
+                .tranches
+                .entry(vt.saturating_add(self.tranche_lock))
+                .or_default();
+            *locked = locked.saturating_add(still_vesting);
+        } else if still_vesting > 0 {
+            self.rebase(t);
+            self.origin_principal = self.origin_principal.saturating_add(still_vesting);
+        }
+        self.total_deposited = self.total_deposited.saturating_add(deposited);
+        self.settle_unchecked(t);
+        self.debug_check_vested(t, before, 0);
+    }
+
+    fn start_cliff(&mut self, t: u64) {
+        if self.cliff_end.is_none() {
+            self.cliff_end = Some(t.saturating_add(self.cliff));
+        }
+    }
+
+    /// Restart the decay curve at `t`.
+    fn rebase(&mut self, t: u64) {
+        let t = self.vesting_time(t);
+        self.apply_due(t);
+        self.origin_principal =
+            self.decay_from(self.curve, self.origin_principal, self.origin_timestamp, t);
+        self.origin_timestamp = t;
+    }
+
+    /// Apply the tranche unlocks and rate changes due by `t`, exactly as queries already do.
+    fn apply_due(&mut self, t: u64) {
+        (self.origin_principal, self.origin_timestamp, self.curve) = self.origin_at(t);
+        self.tranches.retain(|&unlock, _| unlock > t);
+        self.rate_schedule.retain(|&at, _| at > t);
+    }
+
+    fn deposit_unchecked(&mut self, t: u64, amount: u128) {
+        self.accrue_at(t, amount, amount);
+        self.record(t, EventKind::Deposit { amount });
+    }
+
+    fn clawback_unchecked(&mut self, t: u64, amount: u128) -> u128 {
+        let before = self.debug_vested(t);
+        let (tranches, from_curve, _) = self.take_principal(t, amount);
+        let clawed_back = tranches.values().sum::<u128>() + from_curve;
+        self.total_deposited -= clawed_back;
+        self.settle_unchecked(t);
+        self.debug_check_vested(t, before, clawed_back);
+        self.record(
+            t,
+            EventKind::Clawback {
+                amount: clawed_back,
+            },
+        );
+        clawed_back
+    }
+
+    /// Remove up to `amount` of what is still vesting at `t`, starting with the tranches furthest
+    /// from unlocking. Returns the parts taken from the tranches and from the curve, and the
+    /// principal that vests the part from the curve along it from the current origin.
+    fn take_principal(&mut self, t: u64, amount: u128) -> (BTreeMap<u64, u128>, u128, u128) {
+        let mut taken = BTreeMap::new();
+        // Like empty deposits, taking nothing leaves the curve alone.
+        if amount == 0 || self.principal_at(t) == 0 {
+            return (taken, 0, 0);
+        }
+        let vt = self.vesting_time(t);
+        self.apply_due(vt);
+        // Restarting other curves would restart their schedule, so they keep their start and
+        // shrink instead.
+        let restart = self.curve.is_deposit_neutral();
+        if restart {
+            self.rebase(t);
+        }
+        let on_curve =
+            self.decay_from(self.curve, self.origin_principal, self.origin_timestamp, vt);
+        let mut left = amount;
+        while let Some(mut tranche) = self.tranches.last_entry().filter(|_| left > 0) {
+            let take = left.min(*tranche.get());
+            taken.insert(*tranche.key(), take);
+            *tranche.get_mut() -= take;
+            left -= take;
+            if *tranche.get() == 0 {
+                tranche.remove();
+            }
+        }
+        let from_curve = left.min(on_curve);
+        if restart {
+            self.origin_principal -= from_curve;
+            return (taken, from_curve, from_curve);
+        }
+        let part = self.origin_for(vt, from_curve, self.origin_principal);
+        self.origin_principal = self.origin_for(vt, on_curve - from_curve, self.origin_principal);
+        (taken, from_curve, part)
+    }
+
+    /// The largest principal up to `max` that, starting at the current origin, has no more than
+    /// `still_vesting` left at `t`. Each extra unit adds at most one, so this is usually exact.
+    fn origin_for(&self, t: u64, still_vesting: u128, max: u128) -> u128 {
+        let (mut lo, mut hi) = (0, max);
+        while lo < hi {
+            let mid = hi - (hi - lo) / 2;
+            if self.decay_from(self.curve, mid, self.origin_timestamp, t) <= still_vesting {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        lo
+    }
+
+    fn split_unchecked(&mut self, t: u64, amount: u128) -> Self
+    where
+        C: Clone,
+    {
+        let before = self.debug_vested(t);
+        let (tranches, _, origin_principal) = self.take_principal(t, amount);
+        self.total_deposited -= amount;
+        self.settle_unchecked(t);
+        self.debug_check_vested(t, before, 0);
+        self.record(t, EventKind::Split { amount });
+        let mut part = TokenStream {
+            curve: self.curve,
+            rounding: self.rounding,
+            total_deposited: amount,
+            total_claimed: 0,
+            last_update_principal: 0,
+            last_update_timestamp: t,
+            origin_principal,
+            origin_timestamp: self.origin_timestamp,
+            cliff: self.cliff,
+            cliff_end: self.cliff_end,
+            tranche_lock: self.tranche_lock,
+            tranches,
+            rate_schedule: self.rate_schedule.clone(),
+            paused_at: self.paused_at,
+            log: None,
+            clock: self.clock.clone(),
+        };
+        part.settle_unchecked(t);
+        part
+    }
+
+    /// Whether `other` vests exactly like this stream from `t` on, so the two can merge.
+    fn vests_like(&self, other: &Self, t: u64) -> bool {
+        let vt = self.vesting_time(t);
+        let pending = |b: &Self| {
+            b.rate_schedule
+                .range((Bound::Excluded(vt), Bound::Unbounded))
+                .map(|(&at, &half_life)| (at, half_life))
+                .collect::<Vec<_>>()
+        };
+        // Cliffs must end together, unless one has not started or both are over.
+        let cliffs_agree = match (self.cliff_end, other.cliff_end) {
+            (Some(a), Some(b)) => a == b || a.max(b) <= vt,
+            _ => true,
+        };
+        let (mine, theirs) = (self.origin_at(vt), other.origin_at(vt));
+        // Curves that cannot be restarted must also have started together, unless one is empty.
+        let origins_agree =
+            mine.2.is_deposit_neutral() || mine.0 == 0 || theirs.0 == 0 || mine.1 == theirs.1;
+        self.paused_at == other.paused_at
+            && self.rounding == other.rounding
+            && self.tranche_lock == other.tranche_lock
+            && mine.2 == theirs.2
+            && pending(self) == pending(other)
+            && cliffs_agree
+            && origins_agree
+    }
+
+    fn merge_unchecked(&mut self, t: u64, mut other: Self) {
+        debug_assert!(self.vests_like(&other, t));
+        let state = other.snapshot();
+        let before = self.debug_vested(t);
+        if self.cliff_end.is_none() {
+            self.cliff_end = other.cliff_end;
+        }
+        let vt = self.vesting_time(t);
+        self.apply_due(vt);
+        other.apply_due(vt);
+        if self.curve.is_deposit_neutral() {
+            self.rebase(t);
+            other.rebase(t);
+            self.origin_principal = self.origin_principal.saturating_add(other.origin_principal);
+        } else {
+            // Same start, so only the principals add up. Each rounds separately, so search for
+            // the total instead of adding them.
+            if self.origin_principal == 0 {
+                self.origin_timestamp = other.origin_timestamp;
+            }
+            let on_curve =
+                self.decay_from(self.curve, self.origin_principal, self.origin_timestamp, vt)
+                    + other.decay_from(
+                        other.curve,
+                        other.origin_principal,
+                        other.origin_timestamp,
+                        vt,
+                    );
+            let max = self.origin_principal.saturating_add(other.origin_principal);
+            self.origin_principal = self.origin_for(vt, on_curve, max);
+        }
+        for (unlock, amount) in other.tranches {
+            let locked = self.tranches.entry(unlock).or_default();
+            *locked = locked.saturating_add(amount);
+        }
+        self.total_deposited = self.total_deposited.saturating_add(other.total_deposited);
+        self.total_claimed = self.total_claimed.saturating_add(other.total_claimed);
+        self.settle_unchecked(t);
+        self.debug_check_vested(t, before, 0);
+        self.record(
+            t,
+            EventKind::Merge {
+                other: Box::new(state),
+            },
+        );
+    }
+
+    fn claim_unchecked(&mut self, t: u64) -> u128 {
+        if self.is_paused() {
+            return 0;
+        }
+        let before = self.debug_vested(t);
+        let p_now = self.settle_unchecked(t);
         let amt = self
             .total_deposited
             .saturating_sub(self.total_claimed)
             .saturating_sub(p_now); // = (vested - already claimed)
         self.total_claimed = self.total_claimed.saturating_add(amt);
+        self.debug_check_vested(t, before, 0);
+        self.record(t, EventKind::Claim { amount: amt });
         amt
     }
 
-    /// Total still unclaimed.
-    pub fn unclaimed_total(&self) -> u128 {
-        self.total_deposited.saturating_sub(self.total_claimed)
+    fn set_half_life_unchecked(&mut self, t: u64, half_life: HalfLife) {
+        let before = self.debug_vested(t);
+        self.rebase(t);
+        self.settle_unchecked(t);
+        self.curve = ExponentialCurve(DecayRate::from_half_life(half_life)).into();
+        self.debug_check_vested(t, before, 0);
+        self.record(t, EventKind::SetHalfLife { half_life });
+    }
+
+    fn pause_unchecked(&mut self, t: u64) {
+        if self.is_paused() {
+            return;
+        }
+        let before = self.debug_vested(t);
+        self.settle_unchecked(t);
+        self.paused_at = Some(t);
+        self.debug_check_vested(t, before, 0);
+        self.record(t, EventKind::Pause);
+    }
+
+    fn resume_unchecked(&mut self, t: u64) {
+        let Some(paused_at) = self.paused_at else {
+            return;
+        };
+        let before = self.debug_vested(t);
+        self.apply_due(paused_at);
+        let shift = t - paused_at;
+        let later = |at: u64| {
+            if at > paused_at {
+                at.saturating_add(shift)
+            } else {
+                at
+            }
+        };
+        self.origin_timestamp = self.origin_timestamp.saturating_add(shift);
+        self.cliff_end = self.cliff_end.map(later);
+        self.tranches = mem::take(&mut self.tranches)
+            .into_iter()
+            .map(|(unlock, amount)| (later(unlock), amount))
+            .collect();
+        self.rate_schedule = mem::take(&mut self.rate_schedule)
+            .into_iter()
+            .map(|(at, half_life)| (later(at), half_life))
+            .collect();
+        self.paused_at = None;
+        self.settle_unchecked(t);
+        self.debug_check_vested(t, before, 0);
+        self.record(t, EventKind::Resume);
+    }
+
+    /// Only snapshots the state when logging is enabled; the log itself is not part of it.
+    fn record(&mut self, t: u64, kind: EventKind) {
+        if let Some(mut log) = self.log.take() {
+            log.push(Event {
+                timestamp: t,
+                kind,
+                state: self.snapshot(),
+            });
+            self.log = Some(log);
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<C: Clock> serde::Serialize for TokenStream<C> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, C: Clock + Default> serde::Deserialize<'de> for TokenStream<C> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = Snapshot::deserialize(deserializer)?;
+        TokenStream::from_snapshot(snapshot)
+            .map(|b| b.with_clock(C::default()))
+            .map_err(serde::de::Error::custom)
     }
 }